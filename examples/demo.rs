@@ -5,6 +5,8 @@ use nanorand::{Rng, WyRand};
 
 const BASE_VAL: i32 = 10000;
 const CI_INTERVAL: i64 = 1_000_000;
+// IR instructions of a loop iteration when the interrupts are simulated
+const ITERATION_IR: i64 = 1000;
 const MAX_THREADS: i32 = 8;
 
 #[thread_local]
@@ -33,11 +35,17 @@ fn interrupt_handler(ic: i64) {
 }
 
 fn enable_hook() {
-    println!("This function is called after CI is enabled");
+    println!(
+        "This function is called after CI is enabled on {}",
+        std::thread::current().name().unwrap_or_default()
+    );
 }
 
 fn disable_hook() {
-    println!("This function is called before CI is disabled");
+    println!(
+        "This function is called before CI is disabled on {}",
+        std::thread::current().name().unwrap_or_default()
+    );
 }
 
 fn increment() -> Result<()> {
@@ -50,6 +58,8 @@ fn increment() -> Result<()> {
     let iterations = BASE_VAL + (rand() % 10);
     for _ in 0..iterations {
         counter += rand() % 10;
+        // fires the interrupts if the example is run with `CI_SIMULATE=1`
+        compiler_interrupts::sim::tick(ITERATION_IR);
     }
     println!(
         "increment(): thread: {} -> counter: {}",
@@ -97,6 +107,7 @@ fn decrement() -> Result<()> {
 
     for _ in 0..iterations {
         counter -= rand() % 10;
+        compiler_interrupts::sim::tick(ITERATION_IR);
     }
 
     // de-register CI, which effectively disable CI for
@@ -142,7 +153,7 @@ fn main() -> Result<()> {
     for thread_id in 0..num_threads {
         let thread = std::thread::Builder::new()
             .name(format!("inc{}", thread_id))
-            .spawn(|| increment())
+            .spawn(increment)
             .context("failed to create thread")?;
         threads.push(thread);
     }
@@ -156,7 +167,7 @@ fn main() -> Result<()> {
     for thread_id in 0..num_threads {
         let thread = std::thread::Builder::new()
             .name(format!("dec{}", thread_id))
-            .spawn(|| decrement())
+            .spawn(decrement)
             .context("failed to create thread")?;
         threads.push(thread);
     }
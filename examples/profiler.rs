@@ -3,6 +3,7 @@
 #[cfg(target_os = "linux")]
 mod profiler {
     use std::io::Write;
+    use std::ptr::{addr_of, addr_of_mut};
 
     use anyhow::{Context, Result};
     use nanorand::{Rng, WyRand};
@@ -12,6 +13,8 @@ mod profiler {
 
     const BASE_VAL: i64 = 1_000_000;
     const MAX_THREADS: i64 = 2;
+    // IR instructions of a loop iteration when the interrupts are simulated
+    const ITERATION_IR: i64 = 100;
     static mut CI_INTERVAL: i64 = 10_000_000;
 
     #[thread_local]
@@ -42,39 +45,33 @@ mod profiler {
                 panic!("IR count was negative: {}", ic);
             }
 
-            let mut aux: u32 = 0;
-            let curr_tsc = std::arch::x86_64::__rdtscp(&mut aux);
+            let curr_tsc = compiler_interrupts::clock::now();
             let tsc = curr_tsc - prev_tsc;
 
-            buffer_ic.push(ic);
-            buffer_tsc.push(tsc);
+            (*addr_of_mut!(buffer_ic)).push(ic);
+            (*addr_of_mut!(buffer_tsc)).push(tsc);
 
             prev_ic = curr_ic;
             prev_tsc = curr_tsc;
         }
     }
 
-    fn log_intervals() -> Result<()> {
-        unsafe {
-            compiler_interrupts::deregister();
-        }
-
-        let thread = std::thread::current();
-        let thread_name = thread.name().context("failed to get thread name")?;
-
+    fn log_intervals(thread_name: &str) -> Result<()> {
         let filename = format!("{}_intervals.txt", thread_name);
 
         let len: usize;
         let ordered_ic: Vec<i64>;
         let ordered_tsc: Vec<u64>;
         unsafe {
-            len = buffer_tsc.len();
+            let ic = &*addr_of!(buffer_ic);
+            let tsc = &*addr_of!(buffer_tsc);
+            len = tsc.len();
 
             // sort both buffer_ic and buffer_tsc using buffer_tsc as the key
             let mut indicies: Vec<usize> = (0..len).collect();
-            indicies.sort_by_key(|&i| buffer_tsc[i]);
-            ordered_ic = indicies.iter().map(|&i| buffer_ic[i]).collect();
-            ordered_tsc = indicies.iter().map(|&i| buffer_tsc[i]).collect();
+            indicies.sort_by_key(|&i| tsc[i]);
+            ordered_ic = indicies.iter().map(|&i| ic[i]).collect();
+            ordered_tsc = indicies.iter().map(|&i| tsc[i]).collect();
         }
 
         let file = std::fs::File::create(filename)?;
@@ -90,11 +87,12 @@ mod profiler {
         }
 
         unsafe {
-            if !buffer_tsc.is_empty() {
-                let i = buffer_tsc.len() / 2;
+            let tsc = &*addr_of!(buffer_tsc);
+            if !tsc.is_empty() {
+                let i = tsc.len() / 2;
                 println!(
                     "thread: {} -> median interval: {} cycles",
-                    thread_name, buffer_tsc[i]
+                    thread_name, tsc[i]
                 );
             }
         }
@@ -103,10 +101,12 @@ mod profiler {
     }
 
     fn pin_thread() -> Result<()> {
-        // POSIX standard doesn't define `_SC_NPROCESSORS_CONF`
-        let var = unsafe { std::mem::transmute::<i32, SysconfVar>(libc::_SC_NPROCESSORS_CONF) };
-        let max_cpus = sysconf(var)?.context("failed to get number of cpus")?;
-        let cpu = gettid().as_raw() % (max_cpus as i32 - 1);
+        // POSIX standard doesn't define `_SC_NPROCESSORS_CONF`, hence `SysconfVar` lacks it
+        let max_cpus = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_CONF) };
+        if max_cpus < 1 {
+            return Err(std::io::Error::last_os_error()).context("failed to get number of cpus");
+        }
+        let cpu = gettid().as_raw() % (max_cpus as i32 - 1).max(1);
         let mut cpu_set = CpuSet::new();
         cpu_set.set(cpu as usize)?;
         sched_setaffinity(Pid::this(), &cpu_set)?;
@@ -117,25 +117,30 @@ mod profiler {
     fn increment() -> Result<()> {
         pin_thread()?;
 
+        let thread_name = std::thread::current()
+            .name()
+            .context("failed to get thread name")?
+            .to_owned();
+
         unsafe {
             compiler_interrupts::register(CI_INTERVAL, CI_INTERVAL, interrupt_handler);
         }
+        let name = thread_name.clone();
+        compiler_interrupts::on_thread_exit(move || {
+            if let Err(error) = log_intervals(&name) {
+                eprintln!("thread: {} -> failed to log intervals: {}", name, error);
+            }
+        });
 
         let mut counter = 0;
         let iterations = BASE_VAL + (rand() % 10);
         for _ in 0..iterations {
             counter += rand() % 10;
+            // fires the interrupts if the example is run with `CI_SIMULATE=1`
+            compiler_interrupts::sim::tick(ITERATION_IR);
         }
 
-        log_intervals()?;
-
-        println!(
-            "thread: {} -> counter: {}",
-            std::thread::current()
-                .name()
-                .context("failed to get thread name")?,
-            counter
-        );
+        println!("thread: {} -> counter: {}", thread_name, counter);
 
         Ok(())
     }
@@ -149,11 +154,13 @@ mod profiler {
             unsafe {
                 // safety: CI_INTERVAL is only modified once before other threads read it
                 CI_INTERVAL = args[1].parse().unwrap_or(CI_INTERVAL);
-                println!("Using interrupt interval: {} IR", CI_INTERVAL)
+                let interval = CI_INTERVAL;
+                println!("Using interrupt interval: {} IR", interval)
             }
         } else {
             unsafe {
-                println!("Using default interrupt interval: {} IR", CI_INTERVAL);
+                let interval = CI_INTERVAL;
+                println!("Using default interrupt interval: {} IR", interval);
                 println!("To change the interval: ./profiler <interval>");
             }
         }
@@ -167,7 +174,7 @@ mod profiler {
         for thread_id in 0..MAX_THREADS {
             let thread = std::thread::Builder::new()
                 .name(format!("inc{}", thread_id))
-                .spawn(|| increment())
+                .spawn(increment)
                 .expect("failed to create thread");
             threads.push(thread);
         }
@@ -175,7 +182,11 @@ mod profiler {
             thread.join().expect("thread panicked")?;
         }
 
-        log_intervals()?;
+        // the exit callbacks of the main thread may not run, log the intervals by hand
+        unsafe {
+            compiler_interrupts::deregister();
+        }
+        log_intervals("main")?;
 
         println!("Achieved intervals (in cycles) per thread are exported to *_intervals.txt files");
 
@@ -190,5 +201,5 @@ fn main() -> anyhow::Result<()> {
 
 #[cfg(not(target_os = "linux"))]
 fn main() {
-    println!("`profiler` example is available only on x86-64 Linux platforms");
+    println!("`profiler` example is available only on Linux platforms");
 }
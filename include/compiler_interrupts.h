@@ -0,0 +1,41 @@
+#ifndef COMPILER_INTERRUPTS_H
+#define COMPILER_INTERRUPTS_H
+
+/* Generated with cbindgen. Do not edit this file manually. */
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+void ci_register(int64_t ir_interval,
+                 int64_t cycles_interval,
+                 void (*handler)(int64_t, void*),
+                 void *ctx);
+
+void ci_deregister(void);
+
+void ci_enable(void);
+
+void ci_disable(void);
+
+void ci_register_enable_hook(void (*hook)(void));
+
+void ci_deregister_enable_hook(void);
+
+void ci_register_disable_hook(void (*hook)(void));
+
+void ci_deregister_disable_hook(void);
+
+void ci_probe(const char *name);
+
+void instr_enable(void);
+
+void instr_disable(void);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* COMPILER_INTERRUPTS_H */
@@ -0,0 +1,326 @@
+//! Procedural macros for the [`compiler-interrupts`][compiler-interrupts] crate.
+//!
+//! These attributes are re-exported by `compiler-interrupts` with the `macros` feature.
+//!
+//! ``` toml
+//! [dependencies]
+//! compiler-interrupts = { version = "1.0", features = ["macros"] }
+//! ```
+//!
+//! [compiler-interrupts]: https://crates.io/crates/compiler-interrupts
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::{format_ident, quote};
+use syn::parse::Parser;
+use syn::spanned::Spanned;
+use syn::{parse_macro_input, Error, Expr, FnArg, ItemFn, Path, ReturnType, Type};
+
+/// Disables Compiler Interrupts for the whole body of a function.
+///
+/// The body is wrapped in a [`DisableGuard`], hence the interrupts are re-enabled
+/// on every return path, including unwinding.
+///
+/// # Examples
+///
+/// ```
+/// use compiler_interrupts_macros::no_interrupts;
+///
+/// #[no_interrupts]
+/// fn critical_section(values: &mut Vec<i32>) {
+///     values.push(42);
+/// }
+///
+/// let mut values = vec![];
+/// critical_section(&mut values);
+/// ```
+///
+/// [`DisableGuard`]: https://docs.rs/compiler-interrupts/*/compiler_interrupts/struct.DisableGuard.html
+#[proc_macro_attribute]
+pub fn no_interrupts(attr: TokenStream, item: TokenStream) -> TokenStream {
+    if !attr.is_empty() {
+        let attr = proc_macro2::TokenStream::from(attr);
+        return Error::new_spanned(attr, "`no_interrupts` does not take any arguments")
+            .to_compile_error()
+            .into();
+    }
+
+    let mut function = parse_macro_input!(item as ItemFn);
+    if let Some(asyncness) = function.sig.asyncness {
+        return Error::new(
+            asyncness.span(),
+            "`no_interrupts` cannot be used on async functions",
+        )
+        .to_compile_error()
+        .into();
+    }
+
+    let body = &function.block;
+    function.block = syn::parse_quote!({
+        let _ci_guard = unsafe { ::compiler_interrupts::DisableGuard::new() };
+        #body
+    });
+
+    quote!(#function).into()
+}
+
+/// Checks the signature of a Compiler Interrupts handler and generates its registration glue.
+///
+/// The handler must be a plain function taking either the instruction count as `i64`
+/// or a `&mut InterruptContext`. For a handler named `tick`, a
+/// `register_tick(ir_interval, cycles_interval)` function with the same visibility
+/// is generated, which registers the handler with [`register`] or [`register_with_context`].
+///
+/// # Examples
+///
+/// ```
+/// use compiler_interrupts_macros::handler;
+///
+/// #[handler]
+/// fn tick(ic: i64) {
+///     println!("Compiler interrupt called with instruction count: {}", ic);
+/// }
+///
+/// unsafe {
+///     register_tick(10000, 10000);
+/// }
+/// ```
+///
+/// Other signatures are rejected at compile time:
+///
+/// ``` compile_fail
+/// # use compiler_interrupts_macros::handler;
+/// #[handler]
+/// fn tick(ic: u32) {}
+/// ```
+///
+/// ``` compile_fail
+/// # use compiler_interrupts_macros::handler;
+/// #[handler]
+/// fn tick(ic: i64, cycles: i64) {}
+/// ```
+///
+/// ``` compile_fail
+/// # use compiler_interrupts_macros::handler;
+/// #[handler]
+/// fn tick(ic: i64) -> i64 {
+///     ic
+/// }
+/// ```
+///
+/// ``` compile_fail
+/// # use compiler_interrupts_macros::handler;
+/// #[handler]
+/// async fn tick(ic: i64) {}
+/// ```
+///
+/// ``` compile_fail
+/// # use compiler_interrupts_macros::handler;
+/// #[handler]
+/// extern "C" fn tick(ic: i64) {}
+/// ```
+///
+/// [`register`]: https://docs.rs/compiler-interrupts/*/compiler_interrupts/fn.register.html
+/// [`register_with_context`]: https://docs.rs/compiler-interrupts/*/compiler_interrupts/fn.register_with_context.html
+#[proc_macro_attribute]
+pub fn handler(attr: TokenStream, item: TokenStream) -> TokenStream {
+    if !attr.is_empty() {
+        let attr = proc_macro2::TokenStream::from(attr);
+        return Error::new_spanned(attr, "`handler` does not take any arguments")
+            .to_compile_error()
+            .into();
+    }
+
+    let function = parse_macro_input!(item as ItemFn);
+    let kind = match check_handler(&function) {
+        Ok(kind) => kind,
+        Err(error) => return error.to_compile_error().into(),
+    };
+
+    let vis = &function.vis;
+    let name = &function.sig.ident;
+    let register = format_ident!("register_{}", name);
+    let doc = format!("Registers [`{}`] as the Compiler Interrupts handler.", name);
+    let call = match kind {
+        HandlerKind::Count => quote!(::compiler_interrupts::register),
+        HandlerKind::Context => quote!(::compiler_interrupts::register_with_context),
+    };
+
+    quote!(
+        #function
+
+        #[doc = #doc]
+        #vis unsafe fn #register(ir_interval: i64, cycles_interval: i64) {
+            #call(ir_interval, cycles_interval, #name)
+        }
+    )
+    .into()
+}
+
+/// Argument type of a handler.
+enum HandlerKind {
+    /// `fn(i64)`
+    Count,
+    /// `fn(&mut InterruptContext)`
+    Context,
+}
+
+/// Checks that a function can be used as `fn(i64)` or `fn(&mut InterruptContext)`.
+fn check_handler(function: &ItemFn) -> syn::Result<HandlerKind> {
+    let sig = &function.sig;
+    if let Some(constness) = sig.constness {
+        return Err(Error::new(constness.span(), "handler cannot be const"));
+    }
+    if let Some(asyncness) = sig.asyncness {
+        return Err(Error::new(asyncness.span(), "handler cannot be async"));
+    }
+    if let Some(unsafety) = sig.unsafety {
+        return Err(Error::new(unsafety.span(), "handler cannot be unsafe"));
+    }
+    if let Some(abi) = &sig.abi {
+        return Err(Error::new_spanned(abi, "handler must use the Rust ABI"));
+    }
+    if !sig.generics.params.is_empty() {
+        return Err(Error::new_spanned(
+            &sig.generics,
+            "handler cannot be generic",
+        ));
+    }
+    if let Some(variadic) = &sig.variadic {
+        return Err(Error::new_spanned(variadic, "handler cannot be variadic"));
+    }
+
+    let mut inputs = sig.inputs.iter();
+    let arg = match (inputs.next(), inputs.next()) {
+        (Some(FnArg::Typed(arg)), None) => arg,
+        _ => {
+            return Err(Error::new_spanned(
+                &sig.inputs,
+                "handler must take exactly one argument",
+            ))
+        }
+    };
+    let kind = if is_i64(&arg.ty) {
+        HandlerKind::Count
+    } else if is_context(&arg.ty) {
+        HandlerKind::Context
+    } else {
+        return Err(Error::new_spanned(
+            &arg.ty,
+            "handler argument must be `i64` or `&mut InterruptContext`",
+        ));
+    };
+
+    match &sig.output {
+        ReturnType::Default => Ok(kind),
+        ReturnType::Type(_, ty) => match &**ty {
+            Type::Tuple(tuple) if tuple.elems.is_empty() => Ok(kind),
+            ty => Err(Error::new_spanned(ty, "handler cannot return a value")),
+        },
+    }
+}
+
+/// Returns `true` if the type is `i64`.
+fn is_i64(ty: &Type) -> bool {
+    match ty {
+        Type::Path(path) => path.qself.is_none() && path.path.is_ident("i64"),
+        _ => false,
+    }
+}
+
+/// Returns `true` if the type is `&mut InterruptContext`, possibly with a path.
+fn is_context(ty: &Type) -> bool {
+    match ty {
+        Type::Reference(reference) if reference.mutability.is_some() => match &*reference.elem {
+            Type::Path(path) => {
+                path.qself.is_none()
+                    && path
+                        .path
+                        .segments
+                        .last()
+                        .is_some_and(|segment| segment.ident == "InterruptContext")
+            }
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Registers a Compiler Interrupts handler for the duration of a function.
+///
+/// The attribute takes the `interval` for both the IR and cycles interval, an optional
+/// `cycles` interval overriding it, and the `handler` to register. The previous intervals
+/// and handler are restored when the function returns, see [`register_scoped`].
+///
+/// # Examples
+///
+/// ```
+/// use compiler_interrupts_macros::interruptible;
+///
+/// fn tick(ic: i64) {
+///     println!("Compiler interrupt called with instruction count: {}", ic);
+/// }
+///
+/// #[interruptible(interval = 10000, handler = tick)]
+/// fn compute() -> u64 {
+///     (0..1000).sum()
+/// }
+///
+/// assert_eq!(compute(), 499500);
+/// ```
+///
+/// [`register_scoped`]: https://docs.rs/compiler-interrupts/*/compiler_interrupts/fn.register_scoped.html
+#[proc_macro_attribute]
+pub fn interruptible(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let mut interval: Option<Expr> = None;
+    let mut cycles: Option<Expr> = None;
+    let mut handler: Option<Path> = None;
+    let parser = syn::meta::parser(|meta| {
+        if meta.path.is_ident("interval") {
+            interval = Some(meta.value()?.parse()?);
+        } else if meta.path.is_ident("cycles") {
+            cycles = Some(meta.value()?.parse()?);
+        } else if meta.path.is_ident("handler") {
+            handler = Some(meta.value()?.parse()?);
+        } else {
+            return Err(meta.error("expected `interval`, `cycles` or `handler`"));
+        }
+        Ok(())
+    });
+    if let Err(error) = parser.parse(attr) {
+        return error.to_compile_error().into();
+    }
+
+    let mut function = parse_macro_input!(item as ItemFn);
+    if let Some(asyncness) = function.sig.asyncness {
+        return Error::new(
+            asyncness.span(),
+            "`interruptible` cannot be used on async functions",
+        )
+        .to_compile_error()
+        .into();
+    }
+
+    let (interval, handler) = match (interval, handler) {
+        (Some(interval), Some(handler)) => (interval, handler),
+        _ => {
+            return Error::new(
+                Span::call_site(),
+                "`interruptible` requires `interval` and `handler` arguments",
+            )
+            .to_compile_error()
+            .into()
+        }
+    };
+    let cycles = cycles.unwrap_or_else(|| interval.clone());
+
+    let body = &function.block;
+    function.block = syn::parse_quote!({
+        let _ci_registration = unsafe {
+            ::compiler_interrupts::register_scoped(#interval, #cycles, #handler)
+        };
+        #body
+    });
+
+    quote!(#function).into()
+}
@@ -0,0 +1,484 @@
+//! Latency test harness for Compiler Interrupts, modelled on `cyclictest`.
+//!
+//! The harness runs a synthetic workload on pinned threads, each registering a handler
+//! through the `compiler-interrupts` API, and measures the achieved intervals between
+//! interrupts against the configured IR and cycles intervals. The binary must be built
+//! with the Compiler Interrupts pass (e.g. `cargo build-ci --bin ci-latency`) for
+//! the interrupts to fire.
+//!
+//! ``` text
+//! ci-latency [-t threads] [-i ir-interval] [-c cycles-interval] [-l loops]
+//!            [-w compute|memory|mixed] [-j report.json]
+//! ```
+//!
+//! The intervals are measured with [`clock::Native`](compiler_interrupts::clock::Native).
+//! The overshoot over the cycles interval is only reported if the native clock counts
+//! processor cycles, since the cycles interval is in processor cycles.
+
+#[cfg(target_os = "linux")]
+mod latency {
+    use std::cell::RefCell;
+    use std::fmt::Write as _;
+    use std::io;
+    use std::str::FromStr;
+
+    use compiler_interrupts::clock::{self, CycleSource};
+    use compiler_interrupts::Fire;
+
+    const USAGE: &str = "usage: ci-latency [-h] [-t threads] [-i ir-interval] \
+                         [-c cycles-interval] [-l loops] [-w compute|memory|mixed] \
+                         [-j report.json]";
+
+    /// Capacity of the per-thread sample buffer, so the handler never allocates.
+    const MAX_SAMPLES: usize = 1 << 20;
+
+    thread_local! {
+        static SAMPLES: RefCell<Vec<Sample>> = const { RefCell::new(Vec::new()) };
+    }
+
+    /// Achieved interval between two interrupts.
+    #[derive(Clone, Copy)]
+    struct Sample {
+        ir: i64,
+        cycles: u64,
+    }
+
+    /// Synthetic workload of the threads.
+    #[derive(Clone, Copy, Debug)]
+    enum Workload {
+        Compute,
+        Memory,
+        Mixed,
+    }
+
+    impl FromStr for Workload {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            match s {
+                "compute" => Ok(Workload::Compute),
+                "memory" => Ok(Workload::Memory),
+                "mixed" => Ok(Workload::Mixed),
+                _ => Err(format!("unknown workload: {}", s)),
+            }
+        }
+    }
+
+    /// Command-line options.
+    #[derive(Clone, Debug)]
+    struct Options {
+        threads: usize,
+        ir_interval: i64,
+        cycles_interval: i64,
+        loops: u64,
+        workload: Workload,
+        json: Option<String>,
+    }
+
+    impl Options {
+        /// Parses the command-line arguments, returning `None` if the usage is requested.
+        fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Option<Options>, String> {
+            let mut options = Options {
+                threads: 1,
+                ir_interval: 10_000,
+                cycles_interval: 10_000,
+                loops: 10_000_000,
+                workload: Workload::Compute,
+                json: None,
+            };
+
+            while let Some(arg) = args.next() {
+                if arg == "-h" || arg == "--help" {
+                    return Ok(None);
+                }
+                let value = args
+                    .next()
+                    .ok_or_else(|| format!("missing value for {}", arg))?;
+                let invalid = |_| format!("invalid value for {}: {}", arg, value);
+                match arg.as_str() {
+                    "-t" | "--threads" => options.threads = value.parse().map_err(invalid)?,
+                    "-i" | "--ir-interval" => {
+                        options.ir_interval = value.parse().map_err(invalid)?
+                    }
+                    "-c" | "--cycles-interval" => {
+                        options.cycles_interval = value.parse().map_err(invalid)?
+                    }
+                    "-l" | "--loops" => options.loops = value.parse().map_err(invalid)?,
+                    "-w" | "--workload" => options.workload = value.parse()?,
+                    "-j" | "--json" => options.json = Some(value),
+                    _ => return Err(format!("unknown option: {}", arg)),
+                }
+            }
+            if options.threads == 0 {
+                return Err("at least one thread is required".into());
+            }
+
+            Ok(Some(options))
+        }
+    }
+
+    /// Distribution of the achieved intervals of a thread.
+    #[derive(Debug, Default)]
+    struct Report {
+        thread: usize,
+        tid: i32,
+        cpu: usize,
+        fires: usize,
+        ir_min: i64,
+        ir_avg: i64,
+        ir_max: i64,
+        cycles_min: u64,
+        cycles_avg: u64,
+        cycles_p99: u64,
+        cycles_max: u64,
+        cycles_act: u64,
+        /// Maximum cycles over the cycles interval, if the clock counts processor cycles.
+        overshoot_max: Option<u64>,
+    }
+
+    impl Report {
+        fn new(
+            thread: usize,
+            tid: i32,
+            cpu: usize,
+            cycles_interval: i64,
+            samples: &[Sample],
+        ) -> Self {
+            let mut report = Report {
+                thread,
+                tid,
+                cpu,
+                fires: samples.len(),
+                ..Report::default()
+            };
+            if samples.is_empty() {
+                return report;
+            }
+
+            let mut cycles: Vec<u64> = samples.iter().map(|s| s.cycles).collect();
+            let act = cycles[cycles.len() - 1];
+            cycles.sort_unstable();
+            let n = samples.len();
+            report.cycles_min = cycles[0];
+            report.cycles_max = cycles[n - 1];
+            report.cycles_avg =
+                (cycles.iter().map(|&c| c as u128).sum::<u128>() / n as u128) as u64;
+            report.cycles_p99 = cycles[(n - 1) * 99 / 100];
+            report.cycles_act = act;
+            if clock::Native::CPU_CYCLES {
+                report.overshoot_max = Some(
+                    report
+                        .cycles_max
+                        .saturating_sub(cycles_interval.max(0) as u64),
+                );
+            }
+
+            report.ir_min = samples.iter().map(|s| s.ir).min().unwrap_or(0);
+            report.ir_max = samples.iter().map(|s| s.ir).max().unwrap_or(0);
+            report.ir_avg = (samples.iter().map(|s| s.ir as i128).sum::<i128>() / n as i128) as i64;
+
+            report
+        }
+    }
+
+    fn interrupt_handler(fire: &Fire) {
+        SAMPLES.with(|samples| {
+            let mut samples = samples.borrow_mut();
+            if samples.len() < MAX_SAMPLES {
+                samples.push(Sample {
+                    ir: fire.ir_since_last,
+                    cycles: fire.cycles_since_last,
+                });
+            }
+        });
+    }
+
+    fn pin_thread(thread: usize) -> io::Result<usize> {
+        let cpus = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_ONLN) };
+        if cpus < 1 {
+            return Err(io::Error::last_os_error());
+        }
+        let cpu = thread % cpus as usize;
+        unsafe {
+            let mut set: libc::cpu_set_t = std::mem::zeroed();
+            libc::CPU_SET(cpu, &mut set);
+            if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        Ok(cpu)
+    }
+
+    fn run_workload(workload: Workload, loops: u64) -> u64 {
+        let mut memory = vec![0u64; 1 << 16];
+        let mut x: u64 = 0x9e37_79b9_7f4a_7c15;
+        for i in 0..loops {
+            match workload {
+                Workload::Compute => x = x.rotate_left(5) ^ i.wrapping_mul(31),
+                Workload::Memory => {
+                    let slot = (i.wrapping_mul(4099) as usize) & (memory.len() - 1);
+                    memory[slot] = memory[slot].wrapping_add(x);
+                    x = memory[slot];
+                }
+                Workload::Mixed if i % 2 == 0 => x = x.rotate_left(5) ^ i.wrapping_mul(31),
+                Workload::Mixed => {
+                    let slot = (x as usize) & (memory.len() - 1);
+                    memory[slot] ^= i;
+                    x = x.wrapping_add(memory[slot]);
+                }
+            }
+        }
+        std::hint::black_box(x)
+    }
+
+    fn run_thread(thread: usize, options: &Options) -> io::Result<Report> {
+        let cpu = pin_thread(thread)?;
+        let tid = unsafe { libc::gettid() };
+        SAMPLES.with(|samples| samples.borrow_mut().reserve(MAX_SAMPLES));
+
+        unsafe {
+            compiler_interrupts::register_with_fire(
+                options.ir_interval,
+                options.cycles_interval,
+                interrupt_handler,
+            );
+        }
+        run_workload(options.workload, options.loops);
+        unsafe {
+            compiler_interrupts::deregister();
+        }
+
+        let samples = SAMPLES.with(|samples| samples.take());
+        Ok(Report::new(
+            thread,
+            tid,
+            cpu,
+            options.cycles_interval,
+            &samples,
+        ))
+    }
+
+    fn micros(cycles: u64) -> f64 {
+        clock::to_duration(cycles).as_nanos() as f64 / 1000.0
+    }
+
+    fn print_summary(options: &Options, reports: &[Report]) {
+        let cycles_interval = if clock::Native::CPU_CYCLES {
+            format!(
+                "{} ({:.2} us)",
+                options.cycles_interval,
+                micros(options.cycles_interval.max(0) as u64)
+            )
+        } else {
+            options.cycles_interval.to_string()
+        };
+        println!(
+            "# workload: {:?}, IR interval: {}, cycles interval: {}, clock: {} Hz",
+            options.workload,
+            options.ir_interval,
+            cycles_interval,
+            clock::frequency()
+        );
+        for r in reports {
+            let overshoot = r
+                .overshoot_max
+                .map_or_else(|| "-".into(), |over| format!("{:.2}", micros(over)));
+            println!(
+                "T:{:2} ({:6}) CPU:{:3} I:{} C:{:8} Min:{:9.2} Act:{:9.2} Avg:{:9.2} P99:{:9.2} Max:{:9.2} Over:{:>9} IR:{}/{}/{}",
+                r.thread,
+                r.tid,
+                r.cpu,
+                options.ir_interval,
+                r.fires,
+                micros(r.cycles_min),
+                micros(r.cycles_act),
+                micros(r.cycles_avg),
+                micros(r.cycles_p99),
+                micros(r.cycles_max),
+                overshoot,
+                r.ir_min,
+                r.ir_avg,
+                r.ir_max
+            );
+        }
+        if reports.iter().all(|r| r.fires == 0) {
+            println!(
+                "# no interrupts fired, was the binary built with the Compiler Interrupts pass?"
+            );
+        }
+    }
+
+    fn json_report(options: &Options, reports: &[Report]) -> String {
+        let mut json = String::new();
+        let _ = write!(
+            json,
+            "{{\"workload\":\"{:?}\",\"ir_interval\":{},\"cycles_interval\":{},\"loops\":{},\"frequency\":{},\"cpu_cycles\":{},\"threads\":[",
+            options.workload,
+            options.ir_interval,
+            options.cycles_interval,
+            options.loops,
+            clock::frequency(),
+            clock::Native::CPU_CYCLES
+        );
+        for (i, r) in reports.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            let _ = write!(
+                json,
+                "{{\"thread\":{},\"tid\":{},\"cpu\":{},\"fires\":{},\
+                 \"ir\":{{\"min\":{},\"avg\":{},\"max\":{}}},\
+                 \"cycles\":{{\"min\":{},\"avg\":{},\"p99\":{},\"max\":{},\"act\":{}}},\
+                 \"max_overshoot_cycles\":{}}}",
+                r.thread,
+                r.tid,
+                r.cpu,
+                r.fires,
+                r.ir_min,
+                r.ir_avg,
+                r.ir_max,
+                r.cycles_min,
+                r.cycles_avg,
+                r.cycles_p99,
+                r.cycles_max,
+                r.cycles_act,
+                r.overshoot_max
+                    .map_or_else(|| "null".into(), |over| over.to_string())
+            );
+        }
+        json.push_str("]}\n");
+        json
+    }
+
+    pub fn main() -> io::Result<()> {
+        let options = match Options::parse(std::env::args().skip(1)) {
+            Ok(Some(options)) => options,
+            Ok(None) => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            Err(error) => {
+                eprintln!("{}\n{}", error, USAGE);
+                std::process::exit(2);
+            }
+        };
+        clock::calibrate();
+
+        let mut threads = vec![];
+        for thread in 0..options.threads {
+            let options = options.clone();
+            let handle = std::thread::Builder::new()
+                .name(format!("latency{}", thread))
+                .spawn(move || run_thread(thread, &options))?;
+            threads.push(handle);
+        }
+        let mut reports = vec![];
+        for thread in threads {
+            reports.push(thread.join().expect("thread panicked")?);
+        }
+
+        print_summary(&options, &reports);
+        if let Some(path) = &options.json {
+            std::fs::write(path, json_report(&options, &reports))?;
+        }
+
+        Ok(())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn args(args: &[&str]) -> impl Iterator<Item = String> {
+            args.iter()
+                .map(|arg| arg.to_string())
+                .collect::<Vec<_>>()
+                .into_iter()
+        }
+
+        fn samples() -> Vec<Sample> {
+            (1..=100)
+                .map(|i| Sample {
+                    ir: 1000 + i,
+                    cycles: i as u64 * 100,
+                })
+                .collect()
+        }
+
+        #[test]
+        fn parses_options() {
+            assert!(Options::parse(args(&["-h"])).unwrap().is_none());
+            assert!(Options::parse(args(&["-t", "2", "--help"]))
+                .unwrap()
+                .is_none());
+            assert!(Options::parse(args(&["-t"])).is_err());
+            assert!(Options::parse(args(&["-t", "0"])).is_err());
+
+            let options = Options::parse(args(&["-t", "4", "-i", "500", "-w", "memory"]))
+                .unwrap()
+                .unwrap();
+            assert_eq!(options.threads, 4);
+            assert_eq!(options.ir_interval, 500);
+            assert!(matches!(options.workload, Workload::Memory));
+        }
+
+        #[test]
+        fn reports_distribution() {
+            let report = Report::new(1, 42, 0, 5000, &samples());
+            assert_eq!(report.fires, 100);
+            assert_eq!(
+                (report.ir_min, report.ir_avg, report.ir_max),
+                (1001, 1050, 1100)
+            );
+            assert_eq!(report.cycles_min, 100);
+            assert_eq!(report.cycles_avg, 5050);
+            assert_eq!(report.cycles_p99, 9900);
+            assert_eq!(report.cycles_max, 10000);
+            assert_eq!(report.cycles_act, 10000);
+            let overshoot = clock::Native::CPU_CYCLES.then_some(5000);
+            assert_eq!(report.overshoot_max, overshoot);
+
+            let empty = Report::new(0, 42, 0, 5000, &[]);
+            assert_eq!(empty.fires, 0);
+            assert_eq!(empty.overshoot_max, None);
+        }
+
+        #[test]
+        fn renders_json() {
+            let options = Options::parse(args(&["-c", "5000"])).unwrap().unwrap();
+            let reports = [
+                Report::new(0, 42, 0, 5000, &samples()),
+                Report::new(1, 43, 1, 5000, &[]),
+            ];
+            let json: serde_json::Value =
+                serde_json::from_str(&json_report(&options, &reports)).unwrap();
+
+            assert_eq!(json["workload"], "Compute");
+            assert_eq!(json["cycles_interval"], 5000);
+            assert_eq!(json["cpu_cycles"], clock::Native::CPU_CYCLES);
+            let threads = json["threads"].as_array().unwrap();
+            assert_eq!(threads.len(), 2);
+            assert_eq!(threads[0]["tid"], 42);
+            assert_eq!(threads[0]["fires"], 100);
+            assert_eq!(threads[0]["ir"]["avg"], 1050);
+            assert_eq!(threads[0]["cycles"]["p99"], 9900);
+            if clock::Native::CPU_CYCLES {
+                assert_eq!(threads[0]["max_overshoot_cycles"], 5000);
+            } else {
+                assert!(threads[0]["max_overshoot_cycles"].is_null());
+            }
+            assert_eq!(threads[1]["fires"], 0);
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn main() -> std::io::Result<()> {
+    latency::main()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn main() {
+    println!("`ci-latency` is available only on Linux platforms");
+}
@@ -0,0 +1,137 @@
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Token of the innermost [`with_cancellation`] scope on this thread.
+#[allow(non_upper_case_globals)]
+#[thread_local]
+static mut current_token: *const AtomicBool = ptr::null();
+
+/// A token for cancelling a computation from any thread.
+///
+/// The token is checked by the interrupt handler whenever Compiler Interrupts fire
+/// inside [`with_cancellation`]. Clones of a token share the same cancellation state.
+///
+/// # Examples
+///
+/// ```
+/// use compiler_interrupts::CancellationToken;
+///
+/// let token = CancellationToken::new();
+/// let remote = token.clone();
+///
+/// std::thread::spawn(move || remote.cancel()).join().unwrap();
+/// assert!(token.is_cancelled());
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    /// Creates a new token which has not been cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancels the token.
+    ///
+    /// Every computation running inside [`with_cancellation`] with this token
+    /// unwinds at its next interrupt point.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Release);
+    }
+
+    /// Returns `true` if the token has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Acquire)
+    }
+}
+
+/// Error returned by [`with_cancellation`] when the computation was cancelled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("computation was cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// Runs a computation that can be cancelled at interrupt points.
+///
+/// This function takes a cancellation token and a closure to run.
+/// Whenever Compiler Interrupts fire while the closure is running,
+/// the interrupt handler checks the token after the handler from [`register`] returns.
+/// If the token has been cancelled, the closure unwinds and [`Cancelled`] is returned.
+///
+/// # Note
+///
+/// This function is thread-specific, which means the token is only checked
+/// by the interrupts on the thread it is called on. A handler must have been
+/// registered with [`register`] on that thread, otherwise there are no interrupt points.
+///
+/// Cancellation never fires while Compiler Interrupts are disabled by [`disable`],
+/// hence critical sections always run to completion.
+///
+/// Nested calls check the token of the innermost call only.
+///
+/// The computation unwinds from the interrupt point, through the frames of the
+/// instrumented code, hence every frame between this function and the interrupt point
+/// must be Rust code. Unwinding through C or C++ frames is undefined behavior, therefore
+/// the token is not checked while the handler is registered with
+/// [`register_ctx`] or through the C ABI.
+///
+/// # Examples
+///
+/// ```
+/// use compiler_interrupts::{with_cancellation, CancellationToken, Cancelled};
+///
+/// let token = CancellationToken::new();
+/// assert_eq!(with_cancellation(&token, || 42), Ok(42));
+///
+/// token.cancel();
+/// assert_eq!(with_cancellation(&token, || 42), Err(Cancelled));
+/// ```
+///
+/// [`register`]: crate::register
+/// [`register_ctx`]: crate::register_ctx
+/// [`disable`]: crate::disable
+pub fn with_cancellation<F, R>(token: &CancellationToken, f: F) -> Result<R, Cancelled>
+where
+    F: FnOnce() -> R,
+{
+    if token.is_cancelled() {
+        return Err(Cancelled);
+    }
+
+    let result;
+    unsafe {
+        let prev = current_token;
+        current_token = Arc::as_ptr(&token.cancelled);
+        result = panic::catch_unwind(AssertUnwindSafe(f));
+        current_token = prev;
+    }
+
+    match result {
+        Ok(value) => Ok(value),
+        Err(payload) if payload.is::<Cancelled>() => Err(Cancelled),
+        Err(payload) => panic::resume_unwind(payload),
+    }
+}
+
+/// Unwinds the current computation if its token has been cancelled.
+///
+/// # Safety
+///
+/// This function reads a thread-local static variable which uses for the token.
+/// It must only be called from the interrupt handler while the interrupts are enabled.
+pub(crate) unsafe fn check() {
+    if !current_token.is_null() && (*current_token).load(Ordering::Acquire) {
+        panic::resume_unwind(Box::new(Cancelled));
+    }
+}
@@ -0,0 +1,308 @@
+//! Chaos mode which randomizes the interrupt intervals.
+//!
+//! Code which assumes it is not preempted at some point only fails if an interrupt lands
+//! there. In chaos mode, the interrupt function of the framework randomizes the IR and
+//! cycles intervals of the thread after every interrupt, scaling the intervals from
+//! [`register`](crate::register) by a random factor. The intervals later requested with
+//! [`InterruptContext::set_next_interval`](crate::InterruptContext::set_next_interval) or
+//! restored by a [`RegistrationGuard`](crate::RegistrationGuard) are scaled instead. The factors come from a seeded
+//! pseudo-random generator, and the seed is printed to the standard error when the
+//! chaos mode is enabled, so a failing run can be reproduced.
+//!
+//! The chaos mode is enabled with [`Config::install`], or by setting the `CI_CHAOS`
+//! environment variable before the first call to [`register`](crate::register).
+//! The variable contains a seed or `random`, optionally followed by a comma and
+//! a distribution: `uniform`, `exponential` or `burst`.
+//!
+//! ``` text
+//! CI_CHAOS=42,exponential cargo run-ci
+//! ```
+//!
+//! Every thread draws from its own generator, derived from the seed and the order in which
+//! the threads fire their first interrupt. A run with multiple threads is therefore only
+//! reproducible if the threads start in the same order.
+//!
+//! # Examples
+//!
+//! ```
+//! use std::sync::atomic::{AtomicI64, Ordering};
+//!
+//! use compiler_interrupts::chaos::{self, Config, Distribution};
+//! use compiler_interrupts::sim;
+//!
+//! static SHORT: AtomicI64 = AtomicI64::new(0);
+//!
+//! fn interrupt_handler(ic: i64) {
+//!     if ic < 1000 {
+//!         SHORT.fetch_add(1, Ordering::Relaxed);
+//!     }
+//! }
+//!
+//! Config::chaos(42)
+//!     .distribution(Distribution::Exponential)
+//!     .install();
+//!
+//! unsafe {
+//!     compiler_interrupts::register(1000, 1000, interrupt_handler);
+//! }
+//! sim::execute(100_000);
+//! assert!(SHORT.load(Ordering::Relaxed) > 0);
+//!
+//! // the next interrupt restores the intervals from `register`
+//! chaos::uninstall();
+//! ```
+
+use std::env;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+use std::sync::Once;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Environment variable enabling the chaos mode.
+pub const ENV_VAR: &str = "CI_CHAOS";
+
+/// Distribution of the random factors applied to the intervals.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Distribution {
+    /// Factors uniformly distributed between 0 and 2.
+    #[default]
+    Uniform,
+    /// Factors exponentially distributed with a mean of 1,
+    /// giving mostly short intervals with occasional long ones.
+    Exponential,
+    /// Regular intervals interrupted by bursts of 8 interrupts at 1% of the interval,
+    /// starting with a probability of 1/16 after every interrupt.
+    Burst,
+}
+
+impl Distribution {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            1 => Distribution::Exponential,
+            2 => Distribution::Burst,
+            _ => Distribution::Uniform,
+        }
+    }
+
+    fn to_u8(self) -> u8 {
+        match self {
+            Distribution::Uniform => 0,
+            Distribution::Exponential => 1,
+            Distribution::Burst => 2,
+        }
+    }
+}
+
+/// Configuration of the chaos mode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Config {
+    seed: u64,
+    distribution: Distribution,
+}
+
+impl Config {
+    /// Creates a configuration with the given seed and the uniform distribution.
+    pub fn chaos(seed: u64) -> Self {
+        Config {
+            seed,
+            distribution: Distribution::Uniform,
+        }
+    }
+
+    /// Reads the configuration from the `CI_CHAOS` environment variable.
+    ///
+    /// Returns `None` if the variable is not set or is invalid.
+    pub fn from_env() -> Option<Self> {
+        let value = env::var(ENV_VAR).ok()?;
+        let mut parts = value.split(',').map(str::trim);
+        let seed = match parts.next()? {
+            "random" => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_nanos() as u64),
+            seed => seed.parse().ok()?,
+        };
+        let distribution = match parts.next() {
+            None | Some("uniform") => Distribution::Uniform,
+            Some("exponential") => Distribution::Exponential,
+            Some("burst") => Distribution::Burst,
+            Some(_) => return None,
+        };
+        Some(Config::chaos(seed).distribution(distribution))
+    }
+
+    /// Sets the distribution of the random factors.
+    pub fn distribution(mut self, distribution: Distribution) -> Self {
+        self.distribution = distribution;
+        self
+    }
+
+    /// Returns the seed.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Enables the chaos mode on all threads and prints the seed.
+    pub fn install(self) {
+        eprintln!(
+            "compiler-interrupts: chaos mode enabled, reproduce with {}={},{}",
+            ENV_VAR,
+            self.seed,
+            format!("{:?}", self.distribution).to_lowercase()
+        );
+        SEED.store(self.seed, Ordering::Relaxed);
+        DISTRIBUTION.store(self.distribution.to_u8(), Ordering::Relaxed);
+        THREADS.store(0, Ordering::Relaxed);
+        GENERATION.fetch_add(1, Ordering::Relaxed);
+        ENABLED.store(true, Ordering::Release);
+    }
+}
+
+/// Disables the chaos mode on all threads.
+///
+/// The intervals of the threads are restored by their next interrupt, to the intervals
+/// last set by the registration functions or the handlers.
+pub fn uninstall() {
+    ENABLED.store(false, Ordering::Release);
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static SEED: AtomicU64 = AtomicU64::new(0);
+static DISTRIBUTION: AtomicU8 = AtomicU8::new(0);
+/// Number of threads which have seeded their generator.
+static THREADS: AtomicU64 = AtomicU64::new(0);
+/// Incremented by every [`Config::install`], so the threads reseed their generator.
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// Intervals which are randomized, saved by a [`RegistrationGuard`](crate::RegistrationGuard).
+#[derive(Clone, Copy)]
+pub(crate) struct Base {
+    ir_interval: i64,
+    cycles_interval: i64,
+    randomized: bool,
+}
+
+/// Per-thread state of the chaos mode.
+struct State {
+    /// Intervals set through the API of the framework.
+    ir_interval: i64,
+    cycles_interval: i64,
+    /// Whether the intervals have been randomized.
+    randomized: bool,
+    generation: u64,
+    rng: u64,
+    /// Remaining interrupts of the current burst.
+    burst: u32,
+}
+
+#[allow(non_upper_case_globals)]
+#[thread_local]
+static mut state: State = State {
+    ir_interval: 0,
+    cycles_interval: 0,
+    randomized: false,
+    generation: 0,
+    rng: 0,
+    burst: 0,
+};
+
+/// SplitMix64 generator.
+fn next_u64(rng: &mut u64) -> u64 {
+    *rng = rng.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *rng;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Returns a number uniformly distributed in `[0, 1)`.
+fn next_f64(rng: &mut u64) -> f64 {
+    (next_u64(rng) >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Reads the environment variable once, when a handler is registered.
+pub(crate) fn registered() {
+    static ENV: Once = Once::new();
+    ENV.call_once(|| {
+        if let Some(config) = Config::from_env() {
+            config.install();
+        }
+    });
+}
+
+/// Records the intervals set through the API of the framework.
+pub(crate) unsafe fn set_base(ir_interval: i64, cycles_interval: i64) {
+    state.ir_interval = ir_interval;
+    state.cycles_interval = cycles_interval;
+    state.randomized = false;
+}
+
+/// Returns the intervals set through the API of the framework.
+pub(crate) unsafe fn base() -> Base {
+    Base {
+        ir_interval: state.ir_interval,
+        cycles_interval: state.cycles_interval,
+        randomized: state.randomized,
+    }
+}
+
+/// Restores the intervals returned by [`base`].
+pub(crate) unsafe fn restore(base: Base) {
+    state.ir_interval = base.ir_interval;
+    state.cycles_interval = base.cycles_interval;
+    state.randomized = base.randomized;
+}
+
+/// Reseeds the generator of the forking thread in a forked child process,
+/// so the child does not draw the same intervals as the parent.
+pub(crate) fn fork_child() {
+    GENERATION.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Randomizes the intervals of the current thread after an interrupt.
+#[inline]
+pub(crate) unsafe fn after_fire() {
+    if ENABLED.load(Ordering::Relaxed) {
+        randomize();
+    } else if state.randomized {
+        state.randomized = false;
+        crate::write_intervals(state.ir_interval, state.cycles_interval);
+    }
+}
+
+#[cold]
+unsafe fn randomize() {
+    let st = &mut *std::ptr::addr_of_mut!(state);
+    if st.ir_interval <= 0 {
+        return;
+    }
+
+    let generation = GENERATION.load(Ordering::Relaxed);
+    if st.generation != generation {
+        let thread = THREADS.fetch_add(1, Ordering::Relaxed);
+        let mut seed = SEED.load(Ordering::Relaxed) ^ thread.wrapping_mul(0xd6e8_feb8_6659_fd93);
+        next_u64(&mut seed);
+        st.rng = seed;
+        st.generation = generation;
+        st.burst = 0;
+    }
+
+    let rng = &mut st.rng;
+    let factor = match Distribution::from_u8(DISTRIBUTION.load(Ordering::Relaxed)) {
+        Distribution::Uniform => 2.0 * next_f64(rng),
+        Distribution::Exponential => -(1.0 - next_f64(rng)).ln(),
+        Distribution::Burst => {
+            if st.burst == 0 && next_u64(rng).is_multiple_of(16) {
+                st.burst = 8;
+            }
+            if st.burst > 0 {
+                st.burst -= 1;
+                0.01
+            } else {
+                1.0
+            }
+        }
+    };
+
+    let scale = |interval: i64| ((interval as f64 * factor) as i64).max(1);
+    st.randomized = true;
+    crate::write_intervals(scale(st.ir_interval), scale(st.cycles_interval));
+}
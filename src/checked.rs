@@ -0,0 +1,255 @@
+//! Detection of unbalanced [`disable`](crate::disable) and [`enable`](crate::enable) calls.
+//!
+//! In the checked mode, the following mismatches are reported:
+//!
+//! * [`enable`](crate::enable) is called while the interrupts are not disabled.
+//! * A thread exits while the interrupts are disabled.
+//! * [`deregister`](crate::deregister) is called while the interrupts are disabled.
+//!
+//! The checked mode is enabled with the `checked` feature, see [`ENABLED`].
+//! Otherwise, no mismatches are reported.
+//! Mismatches are logged to the standard error by default,
+//! which can be changed with [`set_report`].
+//!
+//! # Examples
+//!
+//! ```
+//! use std::sync::atomic::{AtomicUsize, Ordering};
+//!
+//! use compiler_interrupts::checked::{self, MismatchKind, Report};
+//!
+//! static EXTRA_ENABLES: AtomicUsize = AtomicUsize::new(0);
+//!
+//! checked::set_report(Report::callback(|mismatch| {
+//!     if mismatch.kind == MismatchKind::ExtraEnable {
+//!         EXTRA_ENABLES.fetch_add(1, Ordering::Relaxed);
+//!     }
+//! }));
+//!
+//! unsafe {
+//!     compiler_interrupts::enable();
+//! }
+//! if checked::ENABLED {
+//!     assert_eq!(EXTRA_ENABLES.load(Ordering::Relaxed), 1);
+//! }
+//! ```
+
+use std::backtrace::Backtrace;
+use std::cell::RefCell;
+use std::fmt;
+use std::ptr::addr_of_mut;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+
+/// Whether the mismatches are detected in this build.
+pub const ENABLED: bool = cfg!(feature = "checked");
+
+/// Where mismatches are reported.
+#[derive(Clone)]
+pub enum Report {
+    /// Panics at the mismatched call site.
+    ///
+    /// A panic while a thread exits aborts the process.
+    Panic,
+    /// Logs the mismatch and its backtrace to the standard error.
+    Log,
+    /// Calls a function with the mismatch.
+    Callback(Arc<dyn Fn(&Mismatch) + Send + Sync>),
+}
+
+impl Report {
+    /// Creates a report calling the given function with every mismatch.
+    pub fn callback<F>(f: F) -> Self
+    where
+        F: Fn(&Mismatch) + Send + Sync + 'static,
+    {
+        Report::Callback(Arc::new(f))
+    }
+}
+
+impl fmt::Debug for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Report::Panic => f.write_str("Panic"),
+            Report::Log => f.write_str("Log"),
+            Report::Callback(_) => f.write_str("Callback"),
+        }
+    }
+}
+
+/// Kind of a mismatch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MismatchKind {
+    /// [`enable`](crate::enable) was called while the interrupts were not disabled.
+    ExtraEnable,
+    /// The thread exited while the interrupts were disabled.
+    ExitWhileDisabled {
+        /// Disable depth when the thread exited.
+        depth: u32,
+    },
+    /// [`deregister`](crate::deregister) was called while the interrupts were disabled.
+    DeregisterWhileDisabled {
+        /// Disable depth when the handler was de-registered.
+        depth: u32,
+    },
+}
+
+/// A mismatched call detected in the checked mode.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct Mismatch {
+    /// Kind of the mismatch.
+    pub kind: MismatchKind,
+    /// Name of the thread, if any.
+    pub thread: Option<String>,
+    /// Backtrace of the mismatched call site.
+    ///
+    /// For [`MismatchKind::ExitWhileDisabled`], this is the backtrace of the outermost
+    /// [`disable`](crate::disable) call, which is only captured if backtraces are enabled
+    /// with the `RUST_BACKTRACE` or `RUST_LIB_BACKTRACE` environment variables.
+    pub backtrace: Backtrace,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let thread = self.thread.as_deref().unwrap_or("<unnamed>");
+        match self.kind {
+            MismatchKind::ExtraEnable => write!(
+                f,
+                "thread '{}' enabled Compiler Interrupts which were not disabled",
+                thread
+            ),
+            MismatchKind::ExitWhileDisabled { depth } => write!(
+                f,
+                "thread '{}' exited with Compiler Interrupts disabled (depth {})",
+                thread, depth
+            ),
+            MismatchKind::DeregisterWhileDisabled { depth } => write!(
+                f,
+                "thread '{}' de-registered with Compiler Interrupts disabled (depth {})",
+                thread, depth
+            ),
+        }
+    }
+}
+
+static REPORT: Mutex<Option<Report>> = Mutex::new(None);
+
+/// Report locked by the forking thread.
+#[cfg(unix)]
+#[allow(non_upper_case_globals)]
+#[thread_local]
+static mut forking: Option<MutexGuard<'static, Option<Report>>> = None;
+
+/// Sets where mismatches are reported.
+///
+/// The report applies to all threads. The default report is [`Report::Log`].
+pub fn set_report(report: Report) {
+    *REPORT.lock().unwrap_or_else(|e| e.into_inner()) = Some(report);
+}
+
+/// Outermost critical section of a thread, checked when the thread exits.
+struct Section {
+    named: bool,
+    thread: Option<String>,
+    backtrace: Option<Backtrace>,
+}
+
+impl Drop for Section {
+    fn drop(&mut self) {
+        let depth = unsafe { crate::lc_disabled_count };
+        if depth > 0 {
+            report(Mismatch {
+                kind: MismatchKind::ExitWhileDisabled {
+                    depth: depth as u32,
+                },
+                thread: self.thread.take(),
+                backtrace: self.backtrace.take().unwrap_or_else(Backtrace::disabled),
+            });
+        }
+    }
+}
+
+thread_local! {
+    static SECTION: RefCell<Section> = const {
+        RefCell::new(Section {
+            named: false,
+            thread: None,
+            backtrace: None,
+        })
+    };
+}
+
+fn report(mismatch: Mismatch) {
+    let report = REPORT
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+        .unwrap_or(Report::Log);
+    match report {
+        Report::Panic => panic!("{}", mismatch),
+        Report::Log => eprintln!("{}\n{}", mismatch, mismatch.backtrace),
+        Report::Callback(f) => f(&mismatch),
+    }
+}
+
+fn current(kind: MismatchKind) -> Mismatch {
+    Mismatch {
+        kind,
+        thread: thread::current().name().map(Into::into),
+        backtrace: Backtrace::force_capture(),
+    }
+}
+
+/// Checks a call to [`enable`](crate::enable) while the interrupts are not disabled.
+#[inline]
+pub(crate) fn extra_enable() {
+    if ENABLED {
+        report(current(MismatchKind::ExtraEnable));
+    }
+}
+
+/// Checks a call to [`deregister`](crate::deregister).
+#[inline]
+pub(crate) fn deregistered(depth: i32) {
+    if ENABLED && depth > 0 {
+        report(current(MismatchKind::DeregisterWhileDisabled {
+            depth: depth as u32,
+        }));
+    }
+}
+
+/// Records the outermost [`disable`](crate::disable) call.
+///
+/// The name of the thread is only looked up by the first call on each thread.
+#[inline]
+pub(crate) fn section_started() {
+    if !ENABLED {
+        return;
+    }
+    let _ = SECTION.try_with(|section| {
+        let mut section = section.borrow_mut();
+        if !section.named {
+            section.thread = thread::current().name().map(Into::into);
+            section.named = true;
+        }
+        section.backtrace = Some(Backtrace::capture());
+    });
+}
+
+/// Locks the report before forking.
+#[cfg(unix)]
+pub(crate) fn fork_prepare() {
+    let report = REPORT.lock().unwrap_or_else(|e| e.into_inner());
+    unsafe {
+        forking = Some(report);
+    }
+}
+
+/// Unlocks the report after forking.
+#[cfg(unix)]
+pub(crate) fn fork_release() {
+    unsafe {
+        (*addr_of_mut!(forking)).take();
+    }
+}
@@ -0,0 +1,189 @@
+//! Portable cycle counters.
+//!
+//! The cycles interval of Compiler Interrupts is measured with a cycle counter of the processor.
+//! This module abstracts the counter as a [`CycleSource`]:
+//!
+//! * `Tsc` reads the time-stamp counter on x86-64 platforms.
+//! * `Cntvct` reads the virtual counter `CNTVCT_EL0` on AArch64 platforms.
+//! * [`Monotonic`] reads `clock_gettime(CLOCK_MONOTONIC_RAW)` on other platforms.
+//!
+//! [`Native`] is the best source of the target platform, which is used by
+//! [`now`] and [`frequency`] and for the measurements of [`Fire`](crate::Fire).
+//! Only the time-stamp counter counts processor cycles: the virtual counter ticks at
+//! a fixed frequency of tens of megahertz, and the monotonic clock counts nanoseconds.
+//! Use [`to_duration`] to compare measurements across platforms.
+//!
+//! # Examples
+//!
+//! ```
+//! use compiler_interrupts::clock;
+//!
+//! let start = clock::now();
+//! let sum: u64 = (0..1000).sum();
+//! let elapsed = clock::now().wrapping_sub(start);
+//!
+//! println!("sum {} took {} cycles ({:?})", sum, elapsed, clock::to_duration(elapsed));
+//! ```
+
+use std::time::Duration;
+#[cfg(any(target_arch = "x86_64", not(unix)))]
+use std::time::Instant;
+
+/// A source of cycles.
+pub trait CycleSource {
+    /// Reads the counter.
+    fn now() -> u64;
+
+    /// Returns the frequency of the counter in hertz.
+    ///
+    /// Sources without an architectural frequency are calibrated on the first call,
+    /// which may take a few milliseconds.
+    fn frequency() -> u64;
+
+    /// Whether the counter counts processor cycles,
+    /// like the cycles interval of Compiler Interrupts.
+    const CPU_CYCLES: bool = false;
+}
+
+/// Time-stamp counter of x86-64 processors.
+#[cfg(target_arch = "x86_64")]
+#[derive(Clone, Copy, Debug)]
+pub struct Tsc;
+
+#[cfg(target_arch = "x86_64")]
+impl CycleSource for Tsc {
+    const CPU_CYCLES: bool = true;
+
+    fn now() -> u64 {
+        let mut aux = 0;
+        unsafe { std::arch::x86_64::__rdtscp(&mut aux) }
+    }
+
+    fn frequency() -> u64 {
+        static FREQUENCY: std::sync::OnceLock<u64> = std::sync::OnceLock::new();
+        *FREQUENCY.get_or_init(measure_frequency::<Tsc>)
+    }
+}
+
+/// Virtual counter of AArch64 processors.
+#[cfg(target_arch = "aarch64")]
+#[derive(Clone, Copy, Debug)]
+pub struct Cntvct;
+
+#[cfg(target_arch = "aarch64")]
+impl CycleSource for Cntvct {
+    fn now() -> u64 {
+        let cnt: u64;
+        unsafe {
+            std::arch::asm!("isb", "mrs {}, cntvct_el0", out(reg) cnt, options(nomem, nostack));
+        }
+        cnt
+    }
+
+    fn frequency() -> u64 {
+        let freq: u64;
+        unsafe {
+            std::arch::asm!("mrs {}, cntfrq_el0", out(reg) freq, options(nomem, nostack));
+        }
+        freq
+    }
+}
+
+/// Monotonic clock of the operating system in nanoseconds.
+#[derive(Clone, Copy, Debug)]
+pub struct Monotonic;
+
+#[cfg(unix)]
+impl CycleSource for Monotonic {
+    fn now() -> u64 {
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        const CLOCK: libc::clockid_t = libc::CLOCK_MONOTONIC_RAW;
+        #[cfg(not(any(target_os = "linux", target_os = "android")))]
+        const CLOCK: libc::clockid_t = libc::CLOCK_MONOTONIC;
+
+        let mut ts = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        unsafe {
+            libc::clock_gettime(CLOCK, &mut ts);
+        }
+        ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64
+    }
+
+    fn frequency() -> u64 {
+        1_000_000_000
+    }
+}
+
+#[cfg(not(unix))]
+impl CycleSource for Monotonic {
+    fn now() -> u64 {
+        static ORIGIN: std::sync::OnceLock<Instant> = std::sync::OnceLock::new();
+        ORIGIN.get_or_init(Instant::now).elapsed().as_nanos() as u64
+    }
+
+    fn frequency() -> u64 {
+        1_000_000_000
+    }
+}
+
+/// Best cycle source of the target platform.
+#[cfg(target_arch = "x86_64")]
+pub type Native = Tsc;
+
+/// Best cycle source of the target platform.
+#[cfg(target_arch = "aarch64")]
+pub type Native = Cntvct;
+
+/// Best cycle source of the target platform.
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+pub type Native = Monotonic;
+
+/// Reads the [`Native`] cycle counter.
+pub fn now() -> u64 {
+    Native::now()
+}
+
+/// Returns the frequency of the [`Native`] cycle counter in hertz.
+///
+/// See [`CycleSource::frequency`].
+pub fn frequency() -> u64 {
+    Native::frequency()
+}
+
+/// Calibrates the [`Native`] cycle counter ahead of time.
+///
+/// Calling this function during the start-up of the program avoids
+/// the calibration delay on the first use of [`frequency`] or [`to_duration`].
+pub fn calibrate() {
+    frequency();
+}
+
+/// Converts [`Native`] cycles to a duration.
+pub fn to_duration(cycles: u64) -> Duration {
+    let freq = frequency() as u128;
+    Duration::from_nanos((cycles as u128 * 1_000_000_000 / freq) as u64)
+}
+
+/// Converts a duration to [`Native`] cycles.
+pub fn from_duration(duration: Duration) -> u64 {
+    let freq = frequency() as u128;
+    (duration.as_nanos() * freq / 1_000_000_000) as u64
+}
+
+/// Measures the frequency of a source against the monotonic clock of the standard library.
+#[cfg(target_arch = "x86_64")]
+fn measure_frequency<S: CycleSource>() -> u64 {
+    const PERIOD: Duration = Duration::from_millis(10);
+
+    let start = Instant::now();
+    let begin = S::now();
+    while start.elapsed() < PERIOD {
+        std::hint::spin_loop();
+    }
+    let cycles = S::now().wrapping_sub(begin);
+    let nanos = start.elapsed().as_nanos();
+
+    (cycles as u128 * 1_000_000_000 / nanos).max(1) as u64
+}
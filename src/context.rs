@@ -0,0 +1,177 @@
+use std::thread::{self, ThreadId};
+
+use crate::clock::{self, CycleSource};
+
+/// Cycle counter at the last interrupt.
+#[allow(non_upper_case_globals)]
+#[thread_local]
+static mut prev_cycles: u64 = 0;
+
+/// IR instructions accumulated since the handler was registered.
+#[allow(non_upper_case_globals)]
+#[thread_local]
+pub(crate) static mut total_ir: i64 = 0;
+
+/// Identifier of the thread, cached outside the handler since `thread::current` may allocate.
+#[allow(non_upper_case_globals)]
+#[thread_local]
+static mut current_thread: Option<ThreadId> = None;
+
+/// Resets the counters when a handler is registered.
+///
+/// # Safety
+///
+/// This function mutates thread-local static variables which use for the counters.
+pub(crate) unsafe fn reset() {
+    prev_cycles = clock::now();
+    total_ir = 0;
+    current_thread = Some(thread::current().id());
+}
+
+/// Condition which fired an interrupt.
+///
+/// The runtime does not report why it fired. The reason is inferred by comparing
+/// [`Fire::cycles_since_last`] with the cycles threshold, so it is a heuristic which
+/// can misclassify interrupts, e.g. if the thread was descheduled after the IR interval
+/// elapsed, or if the runtime checked the cycles before the threshold was exceeded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FireReason {
+    /// The IR interval has elapsed.
+    IrInterval,
+    /// The IR interval has elapsed and the cycles since the last interrupt
+    /// have exceeded the cycles threshold.
+    ///
+    /// The threshold is in processor cycles, hence this reason is only reported
+    /// if the [`Native`](clock::Native) source counts processor cycles.
+    CyclesThreshold,
+}
+
+/// Measurements of an interrupt.
+///
+/// The measurements are computed once per interrupt, before the handler is called.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Fire {
+    /// Approximate number of IR instructions since the last interrupt.
+    pub ir_since_last: i64,
+    /// Approximate number of IR instructions since the handler was registered.
+    pub total_ir: i64,
+    /// Number of [`Native`](clock::Native) ticks since the last interrupt, measured by
+    /// [`clock::now`]. The ticks are processor cycles on x86-64 platforms only,
+    /// see [`clock::to_duration`].
+    pub cycles_since_last: u64,
+    /// Condition which fired the interrupt.
+    pub fire_reason: FireReason,
+}
+
+impl Fire {
+    /// Measures an interrupt and updates the counters.
+    ///
+    /// # Safety
+    ///
+    /// This function mutates thread-local static variables which use for the counters.
+    pub(crate) unsafe fn measure(ic: i64) -> Fire {
+        let now = clock::now();
+        let cycles_since_last = now.wrapping_sub(prev_cycles);
+        prev_cycles = now;
+        total_ir += ic;
+
+        let fire_reason = if clock::Native::CPU_CYCLES
+            && crate::ci_cycles_threshold > 0
+            && cycles_since_last >= crate::ci_cycles_threshold as u64
+        {
+            FireReason::CyclesThreshold
+        } else {
+            FireReason::IrInterval
+        };
+
+        Fire {
+            ir_since_last: ic,
+            total_ir,
+            cycles_since_last,
+            fire_reason,
+        }
+    }
+}
+
+/// Context of an interrupt passed to the handler from [`register_with_context`].
+///
+/// The handler requests changes through the context instead of calling the registration
+/// functions itself. The changes are applied after the handler returns in the following order:
+///
+/// 1. the intervals from [`set_next_interval`](InterruptContext::set_next_interval),
+/// 2. the de-registration from [`deregister_after_return`](InterruptContext::deregister_after_return),
+/// 3. the reschedule from [`request_reschedule`](InterruptContext::request_reschedule).
+///
+/// [`register_with_context`]: crate::register_with_context
+#[derive(Debug)]
+pub struct InterruptContext {
+    fire: Fire,
+    next_interval: Option<(i64, i64)>,
+    deregister: bool,
+    reschedule: bool,
+}
+
+impl InterruptContext {
+    /// Creates the context of an interrupt.
+    pub(crate) fn new(fire: Fire) -> Self {
+        InterruptContext {
+            fire,
+            next_interval: None,
+            deregister: false,
+            reschedule: false,
+        }
+    }
+
+    /// Returns the measurements of the interrupt.
+    pub fn fire(&self) -> &Fire {
+        &self.fire
+    }
+
+    /// Returns an approximation of the number of IR instructions since the last interrupt.
+    pub fn ir(&self) -> i64 {
+        self.fire.ir_since_last
+    }
+
+    /// Returns the number of [`Native`](clock::Native) ticks elapsed since the last interrupt,
+    /// see [`Fire::cycles_since_last`].
+    pub fn cycles(&self) -> u64 {
+        self.fire.cycles_since_last
+    }
+
+    /// Returns the identifier of the interrupted thread.
+    pub fn thread_id(&self) -> ThreadId {
+        unsafe { current_thread }.unwrap_or_else(|| thread::current().id())
+    }
+
+    /// Requests the interrupted thread to yield its time slice to the operating system.
+    pub fn request_reschedule(&mut self) {
+        self.reschedule = true;
+    }
+
+    /// Sets the IR interval and cycles interval for the next interrupts.
+    pub fn set_next_interval(&mut self, ir_interval: i64, cycles_interval: i64) {
+        self.next_interval = Some((ir_interval, cycles_interval));
+    }
+
+    /// De-registers the handler once it returns.
+    pub fn deregister_after_return(&mut self) {
+        self.deregister = true;
+    }
+
+    /// Applies the requested changes.
+    ///
+    /// # Safety
+    ///
+    /// This function mutates the thread-local static variables of the framework.
+    pub(crate) unsafe fn apply(self) {
+        if let Some((ir_interval, cycles_interval)) = self.next_interval {
+            crate::set_intervals(ir_interval, cycles_interval);
+        }
+        if self.deregister {
+            crate::deregister_handler();
+        }
+        if self.reschedule {
+            thread::yield_now();
+        }
+    }
+}
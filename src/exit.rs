@@ -0,0 +1,74 @@
+use std::cell::RefCell;
+
+/// Callbacks of a thread, run by the destructor when the thread exits.
+struct Callbacks(Vec<Box<dyn FnOnce()>>);
+
+impl Drop for Callbacks {
+    fn drop(&mut self) {
+        unsafe {
+            if crate::registered {
+                crate::reset_handler();
+            }
+        }
+        for callback in self.0.drain(..).rev() {
+            callback();
+        }
+    }
+}
+
+thread_local! {
+    static CALLBACKS: RefCell<Callbacks> = const { RefCell::new(Callbacks(Vec::new())) };
+}
+
+/// Registers a callback to run when the current thread exits.
+///
+/// When the thread exits, the handler of the thread is de-registered first, then the
+/// callbacks run in the reverse order they were registered. This is the place for
+/// flushing the statistics and recordings of the interrupt handler, which are
+/// no longer updated once the handler is de-registered.
+///
+/// # Note
+///
+/// This function is thread-specific, which means it only registers the callback
+/// on the thread it is called on.
+///
+/// The callbacks run from a thread-local destructor, hence they should not rely on other
+/// thread-local variables with destructors, such as [`std::thread::current`]. If this
+/// function is called while the thread is exiting, the callback runs immediately.
+/// The callbacks of the main thread may not run when the process exits.
+///
+/// # Examples
+///
+/// ```
+/// fn interrupt_handler(ic: i64) {
+///     println!("Compiler interrupt called with instruction count: {}", ic);
+/// }
+///
+/// std::thread::spawn(|| {
+///     unsafe {
+///         compiler_interrupts::register(10000, 10000, interrupt_handler);
+///     }
+///     let name = String::from("worker");
+///     compiler_interrupts::on_thread_exit(move || {
+///         println!("{} exited, interrupts are no longer registered", name);
+///     });
+///
+///     // ...
+/// })
+/// .join()
+/// .unwrap();
+/// ```
+pub fn on_thread_exit<F>(callback: F)
+where
+    F: FnOnce() + 'static,
+{
+    let mut callback = Some(callback);
+    let _ = CALLBACKS.try_with(|callbacks| {
+        if let Some(callback) = callback.take() {
+            callbacks.borrow_mut().0.push(Box::new(callback));
+        }
+    });
+    if let Some(callback) = callback {
+        callback();
+    }
+}
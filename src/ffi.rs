@@ -0,0 +1,221 @@
+//! C ABI for mixed-language programs.
+//!
+//! Programs mixing Rust with C or C++ code instrumented by the Compiler Interrupts pass
+//! must link a single runtime, otherwise the `intvActionHook` thread-local symbol
+//! would be defined twice. The functions of this module are declared in the
+//! `include/compiler_interrupts.h` header, and are exported by a `cdylib` or `staticlib`
+//! built from the crate with `cargo rustc`. The crate itself is only built as an `rlib`,
+//! so Rust dependents do not build the C libraries.
+//!
+//! ``` text
+//! cargo rustc --release --lib --crate-type staticlib
+//! ```
+//!
+//! Each function has the same semantics as its Rust counterpart.
+//!
+//! ``` c
+//! #include <stdio.h>
+//! #include "compiler_interrupts.h"
+//!
+//! static void interrupt_handler(int64_t ic, void *ctx) {
+//!     printf("CI: %lld IR\n", (long long)ic);
+//! }
+//!
+//! int main(void) {
+//!     ci_register(10000, 10000, interrupt_handler, NULL);
+//!     ci_disable();
+//!     // critical section
+//!     ci_enable();
+//!     instr_enable();
+//!     ci_probe("done");
+//!     ci_deregister();
+//! }
+//! ```
+
+use std::ffi::{c_void, CStr};
+use std::os::raw::c_char;
+use std::ptr::addr_of_mut;
+
+use crate::HookId;
+
+/// Hook from [`ci_register_enable_hook`] in the enable hooks of the thread.
+#[allow(non_upper_case_globals)]
+#[thread_local]
+static mut c_enable_hook: Option<HookId> = None;
+
+/// Hook from [`ci_register_disable_hook`] in the disable hooks of the thread.
+#[allow(non_upper_case_globals)]
+#[thread_local]
+static mut c_disable_hook: Option<HookId> = None;
+
+/// Registers a handler for Compiler Interrupts.
+///
+/// See [`register_ctx`](crate::register_ctx). A null handler de-registers the current one.
+///
+/// # Safety
+///
+/// The context pointer must remain valid for as long as the handler is registered.
+#[no_mangle]
+pub unsafe extern "C" fn ci_register(
+    ir_interval: i64,
+    cycles_interval: i64,
+    handler: Option<extern "C" fn(i64, *mut c_void)>,
+    ctx: *mut c_void,
+) {
+    match handler {
+        Some(handler) => crate::register_ctx(ir_interval, cycles_interval, handler, ctx),
+        None => crate::deregister(),
+    }
+}
+
+/// De-registers the handler for Compiler Interrupts.
+///
+/// See [`deregister`](crate::deregister).
+///
+/// # Safety
+///
+/// See [`deregister`](crate::deregister).
+#[no_mangle]
+pub unsafe extern "C" fn ci_deregister() {
+    crate::deregister()
+}
+
+/// Enables Compiler Interrupts.
+///
+/// See [`enable`](crate::enable).
+///
+/// # Safety
+///
+/// See [`enable`](crate::enable).
+#[no_mangle]
+pub unsafe extern "C" fn ci_enable() {
+    crate::enable()
+}
+
+/// Disables Compiler Interrupts.
+///
+/// See [`disable`](crate::disable).
+///
+/// # Safety
+///
+/// See [`disable`](crate::disable).
+#[no_mangle]
+pub unsafe extern "C" fn ci_disable() {
+    crate::disable()
+}
+
+/// Registers a hook when enabling Compiler Interrupts.
+///
+/// The hook is added with [`add_enable_hook`](crate::add_enable_hook), hence it runs
+/// alongside the hooks of Rust code. Consecutive calls replace the previous C hook,
+/// and a null hook de-registers it.
+///
+/// # Safety
+///
+/// See [`register_enable_hook`](crate::register_enable_hook).
+#[no_mangle]
+pub unsafe extern "C" fn ci_register_enable_hook(hook: Option<extern "C" fn()>) {
+    ci_deregister_enable_hook();
+    c_enable_hook = hook.map(|hook| crate::add_enable_hook(move |_| hook()));
+}
+
+/// De-registers the hook when enabling Compiler Interrupts.
+///
+/// This function only removes the hook from [`ci_register_enable_hook`].
+///
+/// # Safety
+///
+/// See [`deregister_enable_hook`](crate::deregister_enable_hook).
+#[no_mangle]
+pub unsafe extern "C" fn ci_deregister_enable_hook() {
+    if let Some(id) = (*addr_of_mut!(c_enable_hook)).take() {
+        crate::remove_hook(id);
+    }
+}
+
+/// Registers a hook when disabling Compiler Interrupts.
+///
+/// The hook is added with [`add_disable_hook`](crate::add_disable_hook), hence it runs
+/// alongside the hooks of Rust code. Consecutive calls replace the previous C hook,
+/// and a null hook de-registers it.
+///
+/// # Safety
+///
+/// See [`register_disable_hook`](crate::register_disable_hook).
+#[no_mangle]
+pub unsafe extern "C" fn ci_register_disable_hook(hook: Option<extern "C" fn()>) {
+    ci_deregister_disable_hook();
+    c_disable_hook = hook.map(|hook| crate::add_disable_hook(move |_| hook()));
+}
+
+/// De-registers the hook when disabling Compiler Interrupts.
+///
+/// This function only removes the hook from [`ci_register_disable_hook`].
+///
+/// # Safety
+///
+/// See [`deregister_disable_hook`](crate::deregister_disable_hook).
+#[no_mangle]
+pub unsafe extern "C" fn ci_deregister_disable_hook() {
+    if let Some(id) = (*addr_of_mut!(c_disable_hook)).take() {
+        crate::remove_hook(id);
+    }
+}
+
+/// Records a hit of a probe if the probe instrumentation is enabled.
+///
+/// See [`Probe::hit`](crate::probe::Probe::hit). Hits with a null or
+/// non-UTF-8 name are ignored.
+///
+/// # Safety
+///
+/// The name must be null or a NUL-terminated string which stays valid
+/// for the rest of the program, such as a string literal.
+#[no_mangle]
+pub unsafe extern "C" fn ci_probe(name: *const c_char) {
+    if !crate::probe::is_enabled() || name.is_null() {
+        return;
+    }
+    let name: &'static CStr = CStr::from_ptr(name);
+    if let Ok(name) = name.to_str() {
+        crate::probe::record(name);
+    }
+}
+
+/// Enables the probe instrumentation.
+///
+/// This function sets the `ci_instr_enabled` flag, hence the probes of the thread
+/// record their hits. See the [`probe`](crate::probe) module for the protocol.
+///
+/// # Note
+///
+/// This function is thread-specific, which means it only enables
+/// on the thread they called on.
+///
+/// # Safety
+///
+/// This function is called outside the normal Rust program.
+/// Rust code can use [`probe::enable`](crate::probe::enable) instead.
+#[no_mangle]
+pub unsafe extern "C" fn instr_enable() {
+    crate::ci_instr_enabled = true;
+}
+
+/// Disables the probe instrumentation.
+///
+/// This function clears the `ci_instr_enabled` flag, hence the probes of the thread
+/// do nothing. See the [`probe`](crate::probe) module for the protocol.
+///
+/// # Note
+///
+/// This function is thread-specific, which means it only disables
+/// on the thread they called on.
+///
+/// # Safety
+///
+/// This function is called outside the normal Rust program.
+/// Rust code can use [`probe::disable`](crate::probe::disable) instead.
+#[no_mangle]
+pub unsafe extern "C" fn instr_disable() {
+    crate::ci_instr_enabled = false;
+}
@@ -0,0 +1,103 @@
+//! Fork safety for Compiler Interrupts.
+//!
+//! After `fork()`, the child process only runs the thread which called it. The crate
+//! registers `pthread_atfork` handlers which keep its process-wide state consistent
+//! in the child:
+//!
+//! * The [`watchdog`](crate::watchdog) registry only keeps the forking thread.
+//!   Monitors and [`ThreadPool`](crate::pool::ThreadPool) workers do not exist in the child,
+//!   hence dropping them in the child does not wait for their threads.
+//! * The `metrics` counters are reset, with the `metrics` feature.
+//! * The [`chaos`](crate::chaos) generator of the forking thread is reseeded, hence the
+//!   child does not repeat the intervals of the parent.
+//! * The pending [`timers`](crate::timers) of the forking thread are cancelled, since
+//!   their callbacks capture the state of the parent. The callbacks are leaked.
+//! * A [`replay`](crate::replay) recording or replay of the forking thread is stopped,
+//!   and the recorded interrupts are discarded.
+//! * The handler of the forking thread is kept or de-registered according to the
+//!   [`ForkPolicy`].
+//!
+//! The handlers are registered by the first call to [`register`](crate::register),
+//! [`watchdog::watch`](crate::watchdog::watch),
+//! [`Watchdog::spawn`](crate::watchdog::Watchdog::spawn) or [`set_policy`].
+//!
+//! This module is available on Unix platforms.
+//!
+//! # Examples
+//!
+//! ```
+//! use compiler_interrupts::fork::{self, ForkPolicy};
+//!
+//! // child processes start without interrupts
+//! fork::set_policy(ForkPolicy::ResetHandler);
+//! ```
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Once;
+
+/// Whether the child process keeps the handler of the forking thread.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum ForkPolicy {
+    /// The child keeps the handler and intervals of the forking thread.
+    ///
+    /// The timers and the replay of the thread are not kept, see the [module](self) docs.
+    #[default]
+    KeepHandler,
+    /// The handler of the forking thread is de-registered in the child.
+    ResetHandler,
+}
+
+static RESET_HANDLER: AtomicBool = AtomicBool::new(false);
+
+/// Sets whether child processes keep the handler of the forking thread.
+///
+/// The policy applies to all threads. The default policy is [`ForkPolicy::KeepHandler`].
+pub fn set_policy(policy: ForkPolicy) {
+    install();
+    RESET_HANDLER.store(policy == ForkPolicy::ResetHandler, Ordering::Relaxed);
+}
+
+/// Returns whether child processes keep the handler of the forking thread.
+pub fn policy() -> ForkPolicy {
+    if RESET_HANDLER.load(Ordering::Relaxed) {
+        ForkPolicy::ResetHandler
+    } else {
+        ForkPolicy::KeepHandler
+    }
+}
+
+/// Registers the `pthread_atfork` handlers once.
+pub(crate) fn install() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| unsafe {
+        libc::pthread_atfork(Some(prepare), Some(parent), Some(child));
+    });
+}
+
+/// Takes the locks of the process-wide state before forking.
+extern "C" fn prepare() {
+    crate::watchdog::fork_prepare();
+    crate::checked::fork_prepare();
+}
+
+/// Releases the locks in the parent process.
+extern "C" fn parent() {
+    crate::checked::fork_release();
+    crate::watchdog::fork_parent();
+}
+
+/// Releases the locks and resets the process-wide state in the child process.
+extern "C" fn child() {
+    crate::checked::fork_release();
+    crate::watchdog::fork_child();
+    #[cfg(feature = "metrics")]
+    crate::metrics::fork_child();
+    crate::chaos::fork_child();
+    crate::timers::fork_child();
+    crate::replay::fork_child();
+    if RESET_HANDLER.load(Ordering::Relaxed) {
+        unsafe {
+            crate::clear_handler();
+        }
+    }
+}
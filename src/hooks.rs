@@ -0,0 +1,351 @@
+use std::cell::RefCell;
+use std::mem;
+use std::ptr::addr_of_mut;
+use std::time::{Duration, Instant};
+
+/// When the enable and disable hooks are called.
+///
+/// The mode applies to the hooks from [`add_enable_hook`] and [`add_disable_hook`]
+/// as well as the hooks from [`register_enable_hook`](crate::register_enable_hook)
+/// and [`register_disable_hook`](crate::register_disable_hook).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum HookMode {
+    /// Hooks are called on every call to [`enable`](crate::enable) and
+    /// [`disable`](crate::disable), including nested ones.
+    #[default]
+    PerCall,
+    /// Hooks are only called when the disable depth goes from 0 to 1 and from 1 to 0,
+    /// which is when entering and leaving the outermost critical section.
+    ///
+    /// The enable hooks receive the time spent with the interrupts disabled.
+    Transition,
+}
+
+/// Hook mode of the current thread.
+#[allow(non_upper_case_globals)]
+#[thread_local]
+static mut current_mode: HookMode = HookMode::PerCall;
+
+/// Start of the current critical section in [`HookMode::Transition`].
+#[allow(non_upper_case_globals)]
+#[thread_local]
+static mut disabled_at: Option<Instant> = None;
+
+/// Identifier of a hook from [`add_enable_hook`] or [`add_disable_hook`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct HookId(u64);
+
+/// Event passed to the hooks from [`add_enable_hook`] and [`add_disable_hook`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct HookEvent {
+    /// Disable depth after the call to [`enable`](crate::enable) or
+    /// [`disable`](crate::disable).
+    ///
+    /// The depth is 0 after the outermost [`enable`](crate::enable),
+    /// and 1 after the outermost [`disable`](crate::disable).
+    pub depth: u32,
+    /// Time spent with the interrupts disabled.
+    ///
+    /// This is only available to the enable hooks in [`HookMode::Transition`].
+    pub disabled_for: Option<Duration>,
+}
+
+type Hook = Box<dyn FnMut(&HookEvent)>;
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Enable,
+    Disable,
+}
+
+struct Hooks {
+    next_id: u64,
+    enable: Vec<(HookId, Hook)>,
+    disable: Vec<(HookId, Hook)>,
+    /// Number of lists currently running.
+    running: u32,
+    /// Hooks removed while their list was running.
+    removed: Vec<HookId>,
+}
+
+impl Hooks {
+    fn list(&mut self, kind: Kind) -> &mut Vec<(HookId, Hook)> {
+        match kind {
+            Kind::Enable => &mut self.enable,
+            Kind::Disable => &mut self.disable,
+        }
+    }
+
+    fn add(&mut self, kind: Kind, hook: Hook) -> HookId {
+        let id = HookId(self.next_id);
+        self.next_id += 1;
+        self.list(kind).push((id, hook));
+        id
+    }
+}
+
+thread_local! {
+    static HOOKS: RefCell<Hooks> = const {
+        RefCell::new(Hooks {
+            next_id: 0,
+            enable: Vec::new(),
+            disable: Vec::new(),
+            running: 0,
+            removed: Vec::new(),
+        })
+    };
+}
+
+/// Puts the running hooks back into their list, even if a hook panics.
+struct Running {
+    kind: Kind,
+    hooks: Vec<(HookId, Hook)>,
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        let hooks = mem::take(&mut self.hooks);
+        let kind = self.kind;
+        // removed hooks are dropped after releasing the borrow
+        let _removed = HOOKS.try_with(|h| {
+            let mut h = h.borrow_mut();
+            let (removed, kept): (Vec<_>, Vec<_>) = hooks
+                .into_iter()
+                .partition(|(id, _)| h.removed.contains(id));
+            h.running -= 1;
+            if h.running == 0 {
+                h.removed.clear();
+            }
+            // hooks added while running go after the existing ones
+            let added = mem::replace(h.list(kind), kept);
+            h.list(kind).extend(added);
+            removed
+        });
+    }
+}
+
+/// Runs the hooks of a kind, enable hooks in FIFO order and disable hooks in LIFO order.
+fn run(kind: Kind, event: HookEvent) {
+    let hooks = HOOKS
+        .try_with(|h| {
+            let mut h = h.borrow_mut();
+            let hooks = mem::take(h.list(kind));
+            if !hooks.is_empty() {
+                h.running += 1;
+            }
+            hooks
+        })
+        .unwrap_or_default();
+    if hooks.is_empty() {
+        return;
+    }
+
+    let mut running = Running { kind, hooks };
+    match kind {
+        Kind::Enable => running.hooks.iter_mut().for_each(|(_, hook)| hook(&event)),
+        Kind::Disable => running
+            .hooks
+            .iter_mut()
+            .rev()
+            .for_each(|(_, hook)| hook(&event)),
+    }
+}
+
+/// Returns `true` if the hooks should be called for a call to
+/// [`enable`](crate::enable) or [`disable`](crate::disable).
+pub(crate) fn should_run(transition: bool) -> bool {
+    transition || hook_mode() == HookMode::PerCall
+}
+
+pub(crate) unsafe fn run_enable(depth: i32, transition: bool) {
+    let disabled_for = match (*addr_of_mut!(disabled_at)).take() {
+        Some(start) if transition => Some(start.elapsed()),
+        _ => None,
+    };
+    let event = HookEvent {
+        depth: depth.max(0) as u32,
+        disabled_for,
+    };
+    run(Kind::Enable, event);
+}
+
+pub(crate) unsafe fn run_disable(depth: i32, transition: bool) {
+    if transition && current_mode == HookMode::Transition {
+        disabled_at = Some(Instant::now());
+    }
+    let event = HookEvent {
+        depth: depth.max(0) as u32,
+        disabled_for: None,
+    };
+    run(Kind::Disable, event);
+}
+
+/// Sets when the enable and disable hooks are called.
+///
+/// The default mode is [`HookMode::PerCall`].
+///
+/// # Note
+///
+/// This function is thread-specific, which means it only sets the mode
+/// of the thread it is called on.
+///
+/// # Examples
+///
+/// ```
+/// use std::cell::Cell;
+/// use std::rc::Rc;
+///
+/// use compiler_interrupts::HookMode;
+///
+/// compiler_interrupts::set_hook_mode(HookMode::Transition);
+/// let calls = Rc::new(Cell::new(0));
+/// let counter = calls.clone();
+/// let id = compiler_interrupts::add_enable_hook(move |event| {
+///     counter.set(counter.get() + 1);
+///     if let Some(time) = event.disabled_for {
+///         println!("interrupts were disabled for {:?}", time);
+///     }
+/// });
+///
+/// unsafe {
+///     compiler_interrupts::disable();
+///     compiler_interrupts::disable();
+///     // no hooks are called here
+///     compiler_interrupts::enable();
+///     // the enable hook is called here
+///     compiler_interrupts::enable();
+/// }
+/// assert_eq!(calls.get(), 1);
+///
+/// compiler_interrupts::remove_hook(id);
+/// ```
+pub fn set_hook_mode(mode: HookMode) {
+    unsafe {
+        current_mode = mode;
+        if mode == HookMode::PerCall {
+            disabled_at = None;
+        }
+    }
+}
+
+/// Returns when the enable and disable hooks are called on the current thread.
+pub fn hook_mode() -> HookMode {
+    unsafe { current_mode }
+}
+
+/// Adds a hook when enabling Compiler Interrupts.
+///
+/// This function takes a closure to be called after enabling Compiler Interrupts,
+/// and returns an identifier for removing it with [`remove_hook`].
+/// Compiler Interrupts can be enabled by calling [`enable`](crate::enable).
+///
+/// Unlike [`register_enable_hook`](crate::register_enable_hook), hooks are added to a list
+/// rather than replacing each other. Enable hooks run in the order they were added,
+/// after the hook from [`register_enable_hook`](crate::register_enable_hook).
+/// The hooks receive the new disable depth, which is 0 after the outermost call.
+///
+/// # Note
+///
+/// This function is thread-specific, which means it only adds the hook
+/// on the thread it is called on.
+///
+/// Calls to [`enable`](crate::enable) and [`disable`](crate::disable) from a hook
+/// do not run the hooks of the same kind again.
+///
+/// # Examples
+///
+/// ```
+/// use compiler_interrupts::HookEvent;
+///
+/// let id = compiler_interrupts::add_enable_hook(|event: &HookEvent| {
+///     if event.depth == 0 {
+///         println!("interrupts have been re-enabled");
+///     }
+/// });
+///
+/// unsafe {
+///     compiler_interrupts::disable();
+///     compiler_interrupts::enable();
+/// }
+///
+/// compiler_interrupts::remove_hook(id);
+/// ```
+pub fn add_enable_hook<F>(hook: F) -> HookId
+where
+    F: FnMut(&HookEvent) + 'static,
+{
+    HOOKS.with(|h| h.borrow_mut().add(Kind::Enable, Box::new(hook)))
+}
+
+/// Adds a hook when disabling Compiler Interrupts.
+///
+/// This function takes a closure to be called before disabling Compiler Interrupts,
+/// and returns an identifier for removing it with [`remove_hook`].
+/// Compiler Interrupts can be temporarily disabled by calling [`disable`](crate::disable).
+///
+/// Unlike [`register_disable_hook`](crate::register_disable_hook), hooks are added to a list
+/// rather than replacing each other. Disable hooks run in the reverse order they were added,
+/// before the hook from [`register_disable_hook`](crate::register_disable_hook).
+/// The hooks receive the new disable depth, which is 1 after the outermost call.
+///
+/// # Note
+///
+/// This function is thread-specific, which means it only adds the hook
+/// on the thread it is called on.
+///
+/// Calls to [`enable`](crate::enable) and [`disable`](crate::disable) from a hook
+/// do not run the hooks of the same kind again.
+///
+/// # Examples
+///
+/// ```
+/// use std::cell::RefCell;
+/// use std::rc::Rc;
+///
+/// let order = Rc::new(RefCell::new(Vec::new()));
+/// let (first, second) = (order.clone(), order.clone());
+/// let a = compiler_interrupts::add_disable_hook(move |_| first.borrow_mut().push("a"));
+/// let b = compiler_interrupts::add_disable_hook(move |_| second.borrow_mut().push("b"));
+///
+/// unsafe {
+///     compiler_interrupts::disable();
+///     compiler_interrupts::enable();
+/// }
+/// assert_eq!(*order.borrow(), ["b", "a"]);
+///
+/// compiler_interrupts::remove_hook(a);
+/// compiler_interrupts::remove_hook(b);
+/// ```
+pub fn add_disable_hook<F>(hook: F) -> HookId
+where
+    F: FnMut(&HookEvent) + 'static,
+{
+    HOOKS.with(|h| h.borrow_mut().add(Kind::Disable, Box::new(hook)))
+}
+
+/// Removes a hook from [`add_enable_hook`] or [`add_disable_hook`].
+///
+/// # Note
+///
+/// This function is thread-specific, which means it only removes the hooks
+/// of the thread it is called on.
+///
+/// A hook which removes itself still finishes its current call.
+pub fn remove_hook(id: HookId) {
+    // the hook is dropped after releasing the borrow
+    let _hook = HOOKS.try_with(|h| {
+        let mut h = h.borrow_mut();
+        for kind in [Kind::Enable, Kind::Disable] {
+            let list = h.list(kind);
+            if let Some(i) = list.iter().position(|(hook, _)| *hook == id) {
+                return Some(list.remove(i));
+            }
+        }
+        // the hook may be running, remove it once its list is put back
+        if h.running > 0 {
+            h.removed.push(id);
+        }
+        None
+    });
+}
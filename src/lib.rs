@@ -7,9 +7,10 @@
 //!
 //! ## Requirements
 //!
-//! * [Rust 1.45.0][rust] or later is required.
-//! Due to the usage of [`#[thread_local]`][thread_local] unstable feature,
-//! this package currently requires nightly Rust.
+//! * Nightly [Rust 1.87.0][rust] or later is required.
+//!   Due to the usage of [`#[thread_local]`][thread_local] unstable feature,
+//!   this package currently requires nightly Rust. The package is tested with
+//!   `nightly-2026-05-19`.
 //!
 //! ## Getting started
 //!
@@ -97,6 +98,46 @@
 
 #![feature(thread_local)]
 
+use std::ffi::c_void;
+use std::marker::PhantomData;
+use std::ptr::{self, addr_of};
+
+mod cancel;
+pub mod chaos;
+pub mod checked;
+pub mod clock;
+mod context;
+mod exit;
+pub mod ffi;
+#[cfg(unix)]
+pub mod fork;
+mod hooks;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod pool;
+pub mod probe;
+mod rate_limit;
+pub mod replay;
+#[cfg(unix)]
+pub mod signal;
+pub mod sim;
+pub mod timers;
+#[cfg(feature = "tracing")]
+pub mod trace;
+pub mod watchdog;
+
+pub use cancel::{with_cancellation, CancellationToken, Cancelled};
+#[cfg(feature = "macros")]
+pub use compiler_interrupts_macros::{handler, interruptible, no_interrupts};
+pub use context::{Fire, FireReason, InterruptContext};
+pub use exit::on_thread_exit;
+pub use ffi::{instr_disable, instr_enable};
+pub use hooks::{
+    add_disable_hook, add_enable_hook, hook_mode, remove_hook, set_hook_mode, HookEvent, HookId,
+    HookMode,
+};
+pub use rate_limit::{LimitedFire, RateLimited};
+
 /// Default large interval
 const LARGE_INTERVAL: i64 = 100000;
 
@@ -113,6 +154,46 @@ static mut intvActionHook: fn(i64) = dummy;
 #[thread_local]
 static mut int_handler: fn(i64) = dummy;
 
+/// Whether a handler is registered by [`register`].
+#[allow(non_upper_case_globals)]
+#[thread_local]
+static mut registered: bool = false;
+
+/// Whether the current thread is running an interrupt handler.
+#[allow(non_upper_case_globals)]
+#[thread_local]
+static mut in_handler: bool = false;
+
+/// Store the interrupt handler from [`register_ctx`].
+#[allow(non_upper_case_globals)]
+#[thread_local]
+static mut ctx_handler: Option<extern "C" fn(i64, *mut c_void)> = None;
+
+/// Store the context pointer from [`register_ctx`].
+#[allow(non_upper_case_globals)]
+#[thread_local]
+static mut ctx_data: *mut c_void = ptr::null_mut();
+
+/// Store the interrupt handler from [`register_with_context`].
+#[allow(non_upper_case_globals)]
+#[thread_local]
+static mut context_handler: fn(&mut InterruptContext) = |_| {};
+
+/// Store the interrupt handler from [`register_with_fire`].
+#[allow(non_upper_case_globals)]
+#[thread_local]
+static mut fire_handler: fn(&Fire) = |_| {};
+
+/// Measurements of the current interrupt.
+#[allow(non_upper_case_globals)]
+#[thread_local]
+static mut last_fire: Fire = Fire {
+    ir_since_last: 0,
+    total_ir: 0,
+    cycles_since_last: 0,
+    fire_reason: FireReason::IrInterval,
+};
+
 /// Store the enable hook from [`register_enable_hook`].
 #[allow(non_upper_case_globals)]
 #[thread_local]
@@ -160,18 +241,143 @@ static mut lc_disabled_count: i32 = 0;
 #[thread_local]
 static mut NextInterval: i32 = 0;
 
+/// Thread-local probe instrumentation flag for the framework.
+#[no_mangle]
+#[thread_local]
+static mut ci_instr_enabled: bool = false;
+
 /// A dummy function.
 fn dummy(_: i64) {}
 
-/// Assigns the interrupt function to itself and calls the handler from [`register`].
+/// Calls the handler from [`register_ctx`] with its context pointer.
+fn ctx_dispatch(ic: i64) {
+    unsafe {
+        if let Some(handler) = ctx_handler {
+            handler(ic, ctx_data);
+        }
+    }
+}
+
+/// Calls the handler from [`register_with_context`] and applies its requested changes.
+fn context_dispatch(_: i64) {
+    unsafe {
+        let mut ctx = InterruptContext::new(last_fire);
+        context_handler(&mut ctx);
+        ctx.apply();
+    }
+}
+
+/// Calls the handler from [`register_with_fire`] with the measurements of the interrupt.
+fn fire_dispatch(_: i64) {
+    unsafe {
+        let fire = last_fire;
+        fire_handler(&fire);
+    }
+}
+
+/// Marks the current thread as running an interrupt handler until dropped.
+///
+/// The previous mark is restored when dropped, also if the handler panics and the panic
+/// is caught, so a nested interrupt does not clear the mark of the outer handler.
+struct HandlerScope {
+    outer: bool,
+}
+
+impl HandlerScope {
+    /// Marks the current thread as running an interrupt handler.
+    fn enter() -> Self {
+        unsafe {
+            let outer = in_handler;
+            in_handler = true;
+            HandlerScope { outer }
+        }
+    }
+}
+
+impl Drop for HandlerScope {
+    fn drop(&mut self) {
+        unsafe {
+            in_handler = self.outer;
+        }
+    }
+}
+
+/// Assigns the interrupt function to itself, measures the interrupt
+/// and calls the handler from [`register`].
+///
+/// The interrupt function is only restored if the handler is still registered
+/// and has not disabled the interrupts. Afterwards, the computation unwinds if it runs
+/// inside [`with_cancellation`] and its token has been cancelled, unless the handler
+/// was registered with [`register_ctx`].
 fn interrupt_handler(ic: i64) {
     unsafe {
+        watchdog::beat();
         intvActionHook = dummy;
+        last_fire = Fire::measure(ic);
+        #[cfg(feature = "metrics")]
+        metrics::fired(ic);
+        let scope = HandlerScope::enter();
         int_handler(ic);
-        intvActionHook = interrupt_handler
+        if registered {
+            chaos::after_fire();
+        }
+        replay::after_fire(ic);
+        timers::poll();
+        drop(scope);
+        if registered && lc_disabled_count == 0 {
+            intvActionHook = interrupt_handler;
+            // unwinding through the frames of a C program is undefined behavior
+            if (*addr_of!(ctx_handler)).is_none() {
+                cancel::check();
+            }
+        }
     }
 }
 
+/// Returns `true` if the current thread is running an interrupt handler.
+///
+/// Code which may run at an arbitrary instrumentation point can use this function
+/// to avoid operations which are unsafe inside interrupt handlers, such as taking locks.
+///
+/// # Note
+///
+/// This function is thread-specific, which means it only checks
+/// the thread it is called on.
+pub fn in_interrupt() -> bool {
+    unsafe { in_handler }
+}
+
+/// Sets the intervals of the framework, which are randomized around them in chaos mode.
+unsafe fn set_intervals(ir_interval: i64, cycles_interval: i64) {
+    write_intervals(ir_interval, cycles_interval);
+    chaos::set_base(ir_interval, cycles_interval);
+}
+
+/// Sets the intervals of the framework without changing the intervals of the chaos mode.
+unsafe fn write_intervals(ir_interval: i64, cycles_interval: i64) {
+    ci_ir_interval = ir_interval;
+    ci_reset_ir_interval = ir_interval / 2;
+    ci_cycles_interval = cycles_interval;
+    ci_cycles_threshold = (0.9 * cycles_interval as f64) as i64;
+}
+
+/// Restarts the IR counter of the instrumented code and the simulated driver,
+/// so that the next interrupt fires after a full IR interval.
+pub(crate) unsafe fn restart_ir_counter() {
+    LocalLC = 0;
+    sim::reset();
+}
+
+/// Checks that the registration is not changed by an interrupt handler.
+#[inline]
+fn assert_not_in_handler() {
+    debug_assert!(
+        !in_interrupt(),
+        "the handler cannot be registered or de-registered inside an interrupt handler, \
+         use `InterruptContext` instead"
+    );
+}
+
 /// Registers a handler for Compiler Interrupts.
 ///
 /// This function takes a IR interval, cycles interval, and
@@ -187,6 +393,10 @@ fn interrupt_handler(ic: i64) {
 /// This function should not be called multiple times.
 /// Consecutive calls will override the previous intervals and handler.
 ///
+/// This function cannot be called inside an interrupt handler, which can request changes
+/// through the [`InterruptContext`] of [`register_with_context`] instead.
+/// Debug builds panic if it is.
+///
 /// # Safety
 ///
 /// This function mutates a thread-local static variable which uses for the interrupt handler.
@@ -205,18 +415,182 @@ fn interrupt_handler(ic: i64) {
 /// }
 /// ```
 pub unsafe fn register(ir_interval: i64, cycles_interval: i64, handler: fn(i64)) {
+    assert_not_in_handler();
+    #[cfg(unix)]
+    fork::install();
     LocalLC += ci_ir_interval as i32;
-    ci_ir_interval = ir_interval;
-    ci_reset_ir_interval = ir_interval / 2;
-    ci_cycles_interval = cycles_interval;
-    ci_cycles_threshold = (0.9 * cycles_interval as f64) as i64;
+    set_intervals(ir_interval, cycles_interval);
+    chaos::registered();
+    context::reset();
+    ctx_handler = None;
+    ctx_data = ptr::null_mut();
+    rate_limit::clear();
     int_handler = handler;
+    registered = true;
     intvActionHook = interrupt_handler;
+    #[cfg(feature = "metrics")]
+    metrics::registered();
+    #[cfg(feature = "tracing")]
+    trace::registered(ir_interval, cycles_interval);
+}
+
+/// Registers a handler with a context pointer for Compiler Interrupts.
+///
+/// This function takes a IR interval, cycles interval,
+/// C-compatible function pointer to the Compiler Interrupts handler and a context pointer.
+/// The handler receives an approximation of the number of IR instructions
+/// since the last interrupt and the given context pointer as the arguments.
+///
+/// # Note
+///
+/// This function is thread-specific, which means it only registers
+/// on the thread they called on.
+///
+/// This function should not be called multiple times.
+/// Consecutive calls will override the previous intervals, handler and context pointer,
+/// including the handler from [`register`].
+///
+/// Computations running inside [`with_cancellation`] are not cancelled while
+/// this handler is registered, since the interrupts may fire in C frames.
+///
+/// # Safety
+///
+/// This function mutates a thread-local static variable which uses for the interrupt handler.
+/// The context pointer must remain valid for as long as the handler is registered,
+/// and it is only ever passed to the handler on the registering thread.
+///
+/// # Examples
+///
+/// ```
+/// use std::ffi::c_void;
+///
+/// extern "C" fn interrupt_handler(ic: i64, ctx: *mut c_void) {
+///     let fires = unsafe { &mut *(ctx as *mut u64) };
+///     *fires += 1;
+///     println!("Compiler interrupt #{} with instruction count: {}", fires, ic);
+/// }
+///
+/// let fires = Box::into_raw(Box::new(0u64));
+/// unsafe {
+///     compiler_interrupts::register_ctx(10000, 10000, interrupt_handler, fires as *mut c_void);
+/// }
+///
+/// // ...
+///
+/// unsafe {
+///     compiler_interrupts::deregister();
+///     drop(Box::from_raw(fires));
+/// }
+/// ```
+pub unsafe fn register_ctx(
+    ir_interval: i64,
+    cycles_interval: i64,
+    handler: extern "C" fn(i64, *mut c_void),
+    ctx: *mut c_void,
+) {
+    assert_not_in_handler();
+    register(ir_interval, cycles_interval, ctx_dispatch);
+    ctx_handler = Some(handler);
+    ctx_data = ctx;
+}
+
+/// Registers a handler receiving an [`InterruptContext`] for Compiler Interrupts.
+///
+/// This function takes a IR interval, cycles interval, and
+/// function pointer to the Compiler Interrupts handler.
+/// The handler receives the context of the interrupt, through which it can
+/// read the IR instructions and cycles since the last interrupt, and request changes
+/// such as the next intervals which are applied after the handler returns.
+///
+/// # Note
+///
+/// This function is thread-specific, which means it only registers
+/// on the thread they called on.
+///
+/// This function should not be called multiple times.
+/// Consecutive calls will override the previous intervals and handler,
+/// including the handler from [`register`].
+///
+/// # Safety
+///
+/// See [`register`].
+///
+/// # Examples
+///
+/// ```
+/// use compiler_interrupts::InterruptContext;
+///
+/// fn interrupt_handler(ctx: &mut InterruptContext) {
+///     println!("{} IR, {} cycles since the last interrupt", ctx.ir(), ctx.cycles());
+///     if ctx.cycles() > 1_000_000 {
+///         // back off for the next interrupts
+///         ctx.set_next_interval(20000, 20000);
+///     }
+/// }
+///
+/// unsafe {
+///     compiler_interrupts::register_with_context(10000, 10000, interrupt_handler);
+/// }
+/// ```
+pub unsafe fn register_with_context(
+    ir_interval: i64,
+    cycles_interval: i64,
+    handler: fn(&mut InterruptContext),
+) {
+    assert_not_in_handler();
+    context_handler = handler;
+    register(ir_interval, cycles_interval, context_dispatch);
+}
+
+/// Registers a handler receiving the measurements of each interrupt for Compiler Interrupts.
+///
+/// This function takes a IR interval, cycles interval, and
+/// function pointer to the Compiler Interrupts handler.
+/// The handler receives the IR instructions and cycles since the last interrupt,
+/// the IR instructions since the registration and whether the IR interval or
+/// the cycles threshold fired the interrupt.
+///
+/// # Note
+///
+/// This function is thread-specific, which means it only registers
+/// on the thread they called on.
+///
+/// This function should not be called multiple times.
+/// Consecutive calls will override the previous intervals and handler,
+/// including the handler from [`register`].
+///
+/// # Safety
+///
+/// See [`register`].
+///
+/// # Examples
+///
+/// ```
+/// use compiler_interrupts::{Fire, FireReason};
+///
+/// fn interrupt_handler(fire: &Fire) {
+///     if fire.fire_reason == FireReason::CyclesThreshold {
+///         println!(
+///             "{} IR and {} cycles since the last interrupt, {} IR in total",
+///             fire.ir_since_last, fire.cycles_since_last, fire.total_ir
+///         );
+///     }
+/// }
+///
+/// unsafe {
+///     compiler_interrupts::register_with_fire(10000, 10000, interrupt_handler);
+/// }
+/// ```
+pub unsafe fn register_with_fire(ir_interval: i64, cycles_interval: i64, handler: fn(&Fire)) {
+    assert_not_in_handler();
+    fire_handler = handler;
+    register(ir_interval, cycles_interval, fire_dispatch);
 }
 
 /// De-registers the handler for Compiler Interrupts.
 ///
-/// This function removes the given interrupts handler from [`register`].
+/// This function removes the given interrupts handler from [`register`], [`register_ctx`],
+/// [`register_with_context`] or [`register_with_fire`].
 ///
 /// # Note
 ///
@@ -226,16 +600,43 @@ pub unsafe fn register(ir_interval: i64, cycles_interval: i64, handler: fn(i64))
 /// This function should not be called multiple times.
 /// Consecutive calls will do nothing as the handler has already been de-registered.
 ///
+/// Like [`register`], this function cannot be called inside an interrupt handler.
+///
 /// # Safety
 ///
 /// This function mutates a thread-local static variable which uses for the interrupt handler.
 /// Thread unsafety will not be introduced. Rust considers mutating static variable unsafe.
 pub unsafe fn deregister() {
+    assert_not_in_handler();
+    deregister_handler();
+}
+
+/// Removes the interrupt handler, which may be running.
+unsafe fn deregister_handler() {
+    checked::deregistered(lc_disabled_count);
+    reset_handler();
+}
+
+/// Removes the interrupt handler without checking the disable depth.
+unsafe fn reset_handler() {
+    clear_handler();
+    #[cfg(feature = "metrics")]
+    metrics::deregistered();
+    #[cfg(feature = "tracing")]
+    trace::deregistered();
+}
+
+/// Removes the interrupt handler without reporting the de-registration.
+unsafe fn clear_handler() {
     ci_ir_interval = LARGE_INTERVAL;
     ci_reset_ir_interval = LARGE_INTERVAL / 2;
     ci_cycles_interval = LARGE_INTERVAL;
     ci_cycles_threshold = (0.9 * LARGE_INTERVAL as f64) as i64;
     int_handler = dummy;
+    registered = false;
+    ctx_handler = None;
+    ctx_data = ptr::null_mut();
+    rate_limit::clear();
     intvActionHook = dummy;
 }
 
@@ -276,14 +677,28 @@ pub unsafe fn deregister() {
 /// }
 /// ```
 pub unsafe fn enable() {
+    let transition = lc_disabled_count == 1;
     if lc_disabled_count > 0 {
         lc_disabled_count -= 1;
+    } else {
+        checked::extra_enable();
     }
-    if let Some(hook) = enableHook {
-        hook();
+    watchdog::set_depth(lc_disabled_count);
+    if hooks::should_run(transition) {
+        if let Some(hook) = enableHook {
+            hook();
+        }
+        hooks::run_enable(lc_disabled_count, transition);
     }
     if lc_disabled_count == 0 {
-        intvActionHook = interrupt_handler;
+        // the interrupt function restores itself after a running handler returns
+        if !in_handler {
+            intvActionHook = interrupt_handler;
+        }
+        #[cfg(feature = "metrics")]
+        metrics::section_ended();
+        #[cfg(feature = "tracing")]
+        trace::leave_section();
     }
 }
 
@@ -322,10 +737,22 @@ pub unsafe fn enable() {
 /// }
 /// ```
 pub unsafe fn disable() {
+    let transition = lc_disabled_count == 0;
     intvActionHook = dummy;
     lc_disabled_count += 1;
-    if let Some(hook) = disableHook {
-        hook();
+    watchdog::set_depth(lc_disabled_count);
+    if lc_disabled_count == 1 {
+        checked::section_started();
+        #[cfg(feature = "metrics")]
+        metrics::section_started();
+        #[cfg(feature = "tracing")]
+        trace::enter_section();
+    }
+    if hooks::should_run(transition) {
+        hooks::run_disable(lc_disabled_count, transition);
+        if let Some(hook) = disableHook {
+            hook();
+        }
     }
 }
 
@@ -341,6 +768,10 @@ pub unsafe fn disable() {
 ///
 /// This function should not be called multiple times.
 /// Consecutive calls will override the previous hook.
+/// Use [`add_enable_hook`] to add multiple hooks.
+///
+/// The hook is called on every call to [`enable`], unless the thread uses
+/// [`HookMode::Transition`] from [`set_hook_mode`].
 ///
 /// # Safety
 ///
@@ -382,6 +813,10 @@ pub unsafe fn deregister_enable_hook() {
 ///
 /// This function should not be called multiple times.
 /// Consecutive calls will override the previous hook.
+/// Use [`add_disable_hook`] to add multiple hooks.
+///
+/// The hook is called on every call to [`disable`], unless the thread uses
+/// [`HookMode::Transition`] from [`set_hook_mode`].
 ///
 /// # Safety
 ///
@@ -411,28 +846,152 @@ pub unsafe fn deregister_disable_hook() {
     disableHook = None
 }
 
-/// Enables the probe instrumentation.
+/// A guard which disables Compiler Interrupts until it is dropped.
 ///
-/// # Note
+/// The guard calls [`disable`] when it is created and [`enable`] when it is dropped,
+/// hence guards can be nested like the functions themselves.
 ///
-/// This function is thread-specific, which means it only enables
-/// on the thread they called on.
+/// # Examples
 ///
-/// # Safety
+/// ```
+/// use compiler_interrupts::DisableGuard;
 ///
-/// This function is called outside the normal Rust program.
-#[no_mangle]
-pub unsafe fn instr_enable() {}
+/// {
+///     let _guard = unsafe { DisableGuard::new() };
+///     println!("interrupts have been disabled");
+/// }
+///
+/// println!("interrupts have been re-enabled");
+/// ```
+#[must_use = "interrupts are re-enabled as soon as the guard is dropped"]
+pub struct DisableGuard {
+    _thread: PhantomData<*const ()>,
+}
+
+impl DisableGuard {
+    /// Disables Compiler Interrupts until the guard is dropped.
+    ///
+    /// # Safety
+    ///
+    /// See [`disable`] and [`enable`].
+    pub unsafe fn new() -> DisableGuard {
+        disable();
+        DisableGuard {
+            _thread: PhantomData,
+        }
+    }
+}
 
-/// Disables the probe instrumentation.
+impl Drop for DisableGuard {
+    fn drop(&mut self) {
+        unsafe {
+            enable();
+        }
+    }
+}
+
+/// A guard which restores the previous registration when it is dropped.
+///
+/// This guard is returned by [`register_scoped`].
+#[must_use = "the previous registration is restored as soon as the guard is dropped"]
+pub struct RegistrationGuard {
+    ir_interval: i64,
+    reset_ir_interval: i64,
+    cycles_interval: i64,
+    cycles_threshold: i64,
+    handler: fn(i64),
+    registered: bool,
+    chaos: chaos::Base,
+    limited: Option<rate_limit::Handler>,
+    ctx_handler: Option<extern "C" fn(i64, *mut c_void)>,
+    ctx_data: *mut c_void,
+    context_handler: fn(&mut InterruptContext),
+    fire_handler: fn(&Fire),
+}
+
+impl Drop for RegistrationGuard {
+    fn drop(&mut self) {
+        unsafe {
+            ci_ir_interval = self.ir_interval;
+            ci_reset_ir_interval = self.reset_ir_interval;
+            ci_cycles_interval = self.cycles_interval;
+            ci_cycles_threshold = self.cycles_threshold;
+            int_handler = self.handler;
+            let deregistered = registered && !self.registered;
+            registered = self.registered;
+            chaos::restore(self.chaos);
+            rate_limit::restore(self.limited.take());
+            ctx_handler = self.ctx_handler;
+            ctx_data = self.ctx_data;
+            context_handler = self.context_handler;
+            fire_handler = self.fire_handler;
+            intvActionHook = if registered && lc_disabled_count == 0 && !in_handler {
+                interrupt_handler
+            } else {
+                dummy
+            };
+            if deregistered {
+                #[cfg(feature = "metrics")]
+                metrics::deregistered();
+                #[cfg(feature = "tracing")]
+                trace::deregistered();
+            }
+        }
+    }
+}
+
+/// Registers a handler for Compiler Interrupts until the returned guard is dropped.
+///
+/// This function behaves like [`register`], except that the previous intervals and handler
+/// are restored when the returned guard is dropped. If no handler was registered before,
+/// the handler is de-registered instead.
 ///
 /// # Note
 ///
-/// This function is thread-specific, which means it only disables
+/// This function is thread-specific, which means it only registers
 /// on the thread they called on.
 ///
+/// Guards must be dropped in the reverse order of their creation.
+///
 /// # Safety
 ///
-/// This function is called outside the normal Rust program.
-#[no_mangle]
-pub unsafe fn instr_disable() {}
+/// See [`register`].
+///
+/// # Examples
+///
+/// ```
+/// fn interrupt_handler(ic: i64) {
+///     println!("Compiler interrupt called with instruction count: {}", ic);
+/// }
+///
+/// {
+///     let _guard = unsafe { compiler_interrupts::register_scoped(10000, 10000, interrupt_handler) };
+///     for _ in 0..42 {
+///         println!("interrupts are registered");
+///     }
+/// }
+///
+/// println!("interrupts are no longer registered");
+/// ```
+pub unsafe fn register_scoped(
+    ir_interval: i64,
+    cycles_interval: i64,
+    handler: fn(i64),
+) -> RegistrationGuard {
+    let guard = RegistrationGuard {
+        ir_interval: ci_ir_interval,
+        reset_ir_interval: ci_reset_ir_interval,
+        cycles_interval: ci_cycles_interval,
+        cycles_threshold: ci_cycles_threshold,
+        handler: int_handler,
+        registered,
+        chaos: chaos::base(),
+        limited: rate_limit::take(),
+        ctx_handler,
+        ctx_data,
+        context_handler,
+        fire_handler,
+    };
+    register(ir_interval, cycles_interval, handler);
+    guard
+}
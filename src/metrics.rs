@@ -0,0 +1,282 @@
+//! Process-wide metrics of Compiler Interrupts.
+//!
+//! The interrupt function of the framework, [`register`](crate::register),
+//! [`deregister`](crate::deregister), [`disable`](crate::disable) and
+//! [`enable`](crate::enable) aggregate the counters of every thread into process-wide
+//! atomics. The metrics are rendered in the Prometheus text exposition format by
+//! [`render`], and can be served by a minimal HTTP endpoint with [`serve`].
+//!
+//! The metrics are not broken down per thread. Every thread adds to the same counters,
+//! hence the metrics describe the whole process, and the fires of a single thread
+//! cannot be told apart from the others.
+//!
+//! This module is available with the `metrics` feature.
+//!
+//! | Metric | Type | Description |
+//! | ------ | ---- | ----------- |
+//! | `ci_fires_total` | counter | Interrupts fired |
+//! | `ci_ir_interval` | summary | IR instructions between interrupts |
+//! | `ci_ir_interval_average` | gauge | Average IR instructions between interrupts |
+//! | `ci_disabled_sections_total` | counter | Outermost critical sections |
+//! | `ci_disabled_seconds_total` | counter | Time spent with interrupts disabled |
+//! | `ci_registrations_total` | counter | Calls to the registration functions |
+//! | `ci_deregistrations_total` | counter | De-registrations by [`deregister`](crate::deregister) or a [`RegistrationGuard`](crate::RegistrationGuard) |
+//!
+//! # Examples
+//!
+//! ```
+//! use compiler_interrupts::{metrics, sim};
+//!
+//! fn interrupt_handler(_ic: i64) {}
+//!
+//! unsafe {
+//!     compiler_interrupts::register(1000, 1000, interrupt_handler);
+//! }
+//! sim::execute(10000);
+//!
+//! let text = metrics::render();
+//! assert!(text.contains("ci_fires_total"));
+//! ```
+
+use std::fmt::Write as _;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::ptr::addr_of_mut;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Time given to a client of [`serve`] to send its request and receive the response.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+static FIRES: AtomicU64 = AtomicU64::new(0);
+static IR_SUM: AtomicU64 = AtomicU64::new(0);
+static DISABLED_SECTIONS: AtomicU64 = AtomicU64::new(0);
+static DISABLED_NANOS: AtomicU64 = AtomicU64::new(0);
+static REGISTRATIONS: AtomicU64 = AtomicU64::new(0);
+static DEREGISTRATIONS: AtomicU64 = AtomicU64::new(0);
+
+/// Start of the current critical section.
+#[allow(non_upper_case_globals)]
+#[thread_local]
+static mut disabled_at: Option<Instant> = None;
+
+/// Snapshot of the metrics.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Snapshot {
+    /// Interrupts fired.
+    pub fires: u64,
+    /// Sum of the IR instructions between interrupts.
+    pub ir_sum: u64,
+    /// Outermost critical sections.
+    pub disabled_sections: u64,
+    /// Nanoseconds spent with interrupts disabled.
+    pub disabled_nanos: u64,
+    /// Calls to the registration functions.
+    pub registrations: u64,
+    /// De-registrations by [`deregister`](crate::deregister)
+    /// or a [`RegistrationGuard`](crate::RegistrationGuard).
+    pub deregistrations: u64,
+}
+
+impl Snapshot {
+    /// Returns the average IR instructions between interrupts.
+    pub fn average_ir_interval(&self) -> f64 {
+        if self.fires == 0 {
+            0.0
+        } else {
+            self.ir_sum as f64 / self.fires as f64
+        }
+    }
+}
+
+/// Returns a snapshot of the metrics.
+pub fn snapshot() -> Snapshot {
+    Snapshot {
+        fires: FIRES.load(Ordering::Relaxed),
+        ir_sum: IR_SUM.load(Ordering::Relaxed),
+        disabled_sections: DISABLED_SECTIONS.load(Ordering::Relaxed),
+        disabled_nanos: DISABLED_NANOS.load(Ordering::Relaxed),
+        registrations: REGISTRATIONS.load(Ordering::Relaxed),
+        deregistrations: DEREGISTRATIONS.load(Ordering::Relaxed),
+    }
+}
+
+/// Renders the metrics in the Prometheus text exposition format.
+pub fn render() -> String {
+    let s = snapshot();
+    let mut text = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, samples: &[(&str, String)]| {
+        let _ = writeln!(text, "# HELP {} {}", name, help);
+        let _ = writeln!(text, "# TYPE {} {}", name, kind);
+        for (suffix, value) in samples {
+            let _ = writeln!(text, "{}{} {}", name, suffix, value);
+        }
+    };
+
+    metric(
+        "ci_fires_total",
+        "counter",
+        "Compiler Interrupts fired.",
+        &[("", s.fires.to_string())],
+    );
+    metric(
+        "ci_ir_interval",
+        "summary",
+        "IR instructions between Compiler Interrupts.",
+        &[
+            ("_sum", s.ir_sum.to_string()),
+            ("_count", s.fires.to_string()),
+        ],
+    );
+    metric(
+        "ci_ir_interval_average",
+        "gauge",
+        "Average IR instructions between Compiler Interrupts.",
+        &[("", s.average_ir_interval().to_string())],
+    );
+    metric(
+        "ci_disabled_sections_total",
+        "counter",
+        "Outermost sections with Compiler Interrupts disabled.",
+        &[("", s.disabled_sections.to_string())],
+    );
+    metric(
+        "ci_disabled_seconds_total",
+        "counter",
+        "Time spent with Compiler Interrupts disabled.",
+        &[("", (s.disabled_nanos as f64 / 1e9).to_string())],
+    );
+    metric(
+        "ci_registrations_total",
+        "counter",
+        "Registrations of Compiler Interrupts handlers.",
+        &[("", s.registrations.to_string())],
+    );
+    metric(
+        "ci_deregistrations_total",
+        "counter",
+        "De-registrations of Compiler Interrupts handlers.",
+        &[("", s.deregistrations.to_string())],
+    );
+
+    text
+}
+
+/// Serves the metrics over HTTP on the given address.
+///
+/// This function binds the address and spawns a thread answering every request with
+/// the output of [`render`]. The endpoint is meant for local scraping and tests, and
+/// serves the requests one at a time for the rest of the process. A client which does not
+/// send its request within a few seconds is disconnected.
+///
+/// Returns the bound address, which is useful when binding port 0.
+///
+/// # Examples
+///
+/// ```
+/// use std::io::{Read, Write};
+/// use std::net::TcpStream;
+///
+/// use compiler_interrupts::metrics;
+///
+/// let addr = metrics::serve("127.0.0.1:0").unwrap();
+///
+/// let mut stream = TcpStream::connect(addr).unwrap();
+/// stream.write_all(b"GET /metrics HTTP/1.1\r\n\r\n").unwrap();
+/// let mut response = String::new();
+/// stream.read_to_string(&mut response).unwrap();
+/// assert!(response.starts_with("HTTP/1.1 200 OK"));
+/// assert!(response.contains("ci_fires_total"));
+/// ```
+pub fn serve<A: ToSocketAddrs>(addr: A) -> io::Result<SocketAddr> {
+    let listener = TcpListener::bind(addr)?;
+    let addr = listener.local_addr()?;
+    thread::Builder::new()
+        .name("ci-metrics".into())
+        .spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let _ = respond(stream);
+            }
+        })?;
+
+    Ok(addr)
+}
+
+/// Answers a request with the metrics.
+fn respond(mut stream: TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let deadline = Instant::now() + TIMEOUT;
+
+    // the request itself is ignored, read until the end of the headers
+    let mut request = Vec::new();
+    let mut buf = [0; 512];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        if Instant::now() >= deadline {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        let n = stream.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+
+    let body = render();
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: text/plain; version=0.0.4\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n{}",
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+/// Records an interrupt.
+#[inline]
+pub(crate) fn fired(ic: i64) {
+    FIRES.fetch_add(1, Ordering::Relaxed);
+    IR_SUM.fetch_add(ic.max(0) as u64, Ordering::Relaxed);
+}
+
+/// Records a registration.
+pub(crate) fn registered() {
+    REGISTRATIONS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Records a de-registration.
+pub(crate) fn deregistered() {
+    DEREGISTRATIONS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Records the start of an outermost critical section.
+pub(crate) unsafe fn section_started() {
+    disabled_at = Some(Instant::now());
+}
+
+/// Records the end of an outermost critical section.
+pub(crate) unsafe fn section_ended() {
+    if let Some(start) = (*addr_of_mut!(disabled_at)).take() {
+        DISABLED_SECTIONS.fetch_add(1, Ordering::Relaxed);
+        DISABLED_NANOS.fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+    }
+}
+
+/// Resets the metrics in a forked child process.
+pub(crate) fn fork_child() {
+    for counter in [
+        &FIRES,
+        &IR_SUM,
+        &DISABLED_SECTIONS,
+        &DISABLED_NANOS,
+        &REGISTRATIONS,
+        &DEREGISTRATIONS,
+    ] {
+        counter.store(0, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,339 @@
+//! Fairness-aware thread pool which preempts long-running jobs on interrupts.
+//!
+//! Every worker registers a Compiler Interrupts handler for the time slice of the pool.
+//! Jobs are resumable closures returning [`Step`]: a job checks [`should_yield`] at
+//! convenient points and returns [`Step::Yield`] once its slice has expired, keeping its
+//! progress in the captured state. The worker then moves the job to the back of its deque,
+//! so short jobs are no longer stuck behind long ones.
+//!
+//! Every worker has its own deque. Spawned jobs are distributed across the deques in turn,
+//! and a worker takes jobs from the front of its deque. A worker with an empty deque steals
+//! a job from the back of the deque of another worker.
+//!
+//! # Examples
+//!
+//! ```
+//! use compiler_interrupts::pool::{self, Step, ThreadPool};
+//!
+//! let pool = ThreadPool::new(2, 10000).expect("failed to create thread pool");
+//!
+//! let mut i = 0u64;
+//! let mut sum = 0u64;
+//! let job = pool.spawn(move || {
+//!     while i < 1_000_000 {
+//!         sum += i;
+//!         i += 1;
+//!         if pool::should_yield() {
+//!             return Step::Yield;
+//!         }
+//!     }
+//!     Step::Done
+//! });
+//!
+//! let stats = job.join().expect("job panicked");
+//! println!("job took {} slices", stats.slices);
+//! ```
+
+use std::any::Any;
+use std::collections::VecDeque;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+
+/// Whether the slice of the running job has expired.
+#[allow(non_upper_case_globals)]
+#[thread_local]
+static mut slice_expired: bool = false;
+
+/// IR instructions accumulated by the running job.
+#[allow(non_upper_case_globals)]
+#[thread_local]
+static mut slice_ir: i64 = 0;
+
+/// Interrupt handler of the workers.
+fn slice_handler(ic: i64) {
+    unsafe {
+        slice_ir += ic;
+        slice_expired = true;
+    }
+}
+
+/// Returns `true` if the time slice of the running job has expired.
+///
+/// Jobs should return [`Step::Yield`] as soon as possible after this function returns `true`.
+/// Outside of a pool worker, this function always returns `false`.
+pub fn should_yield() -> bool {
+    unsafe { slice_expired }
+}
+
+/// Result of running a job for one slice.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Step {
+    /// The job has more work to do and should be resumed later.
+    Yield,
+    /// The job has completed.
+    Done,
+}
+
+/// Slice statistics of a job.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct JobStats {
+    /// Number of slices the job has run for.
+    pub slices: u64,
+    /// Number of times the job yielded and was moved to the back of a deque.
+    pub yields: u64,
+    /// Approximate number of IR instructions the job has executed.
+    pub ir: i64,
+}
+
+/// Completion state of a job.
+#[derive(Default)]
+struct JobState {
+    stats: JobStats,
+    done: bool,
+    panic: Option<Box<dyn Any + Send>>,
+}
+
+/// State shared between a job, its handle and the workers.
+#[derive(Default)]
+struct JobShared {
+    state: Mutex<JobState>,
+    finished: Condvar,
+}
+
+/// A queued job.
+struct Task {
+    job: Box<dyn FnMut() -> Step + Send>,
+    shared: Arc<JobShared>,
+}
+
+/// State shared between a pool and its workers.
+struct Shared {
+    /// Deque of every worker.
+    queues: Vec<Mutex<VecDeque<Task>>>,
+    /// Worker receiving the next spawned job.
+    next_queue: AtomicUsize,
+    /// Whether the pool is shutting down, which is also the lock of the idle workers.
+    shutdown: Mutex<bool>,
+    available: Condvar,
+}
+
+impl Shared {
+    /// Pushes a job to the back of the deque of a worker and wakes up an idle worker.
+    fn push(&self, queue: usize, task: Task) {
+        lock(&self.queues[queue]).push_back(task);
+        // the idle workers check the deques while holding the lock
+        let _shutdown = lock(&self.shutdown);
+        self.available.notify_one();
+    }
+
+    /// Takes a job from the front of the deque of a worker,
+    /// or steals one from the back of the deque of another worker.
+    fn pop(&self, queue: usize) -> Option<Task> {
+        if let Some(task) = lock(&self.queues[queue]).pop_front() {
+            return Some(task);
+        }
+        let len = self.queues.len();
+        (1..len).find_map(|i| lock(&self.queues[(queue + i) % len]).pop_back())
+    }
+}
+
+/// Locks a mutex, ignoring poisoning since jobs never run under the lock.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// A handle to a job spawned on a [`ThreadPool`].
+pub struct JobHandle {
+    shared: Arc<JobShared>,
+}
+
+impl JobHandle {
+    /// Returns the statistics of the job so far.
+    pub fn stats(&self) -> JobStats {
+        lock(&self.shared.state).stats
+    }
+
+    /// Returns `true` if the job has completed.
+    pub fn is_finished(&self) -> bool {
+        lock(&self.shared.state).done
+    }
+
+    /// Waits for the job to complete and returns its statistics.
+    ///
+    /// If the job panicked, the panic payload is returned as an error.
+    pub fn join(self) -> thread::Result<JobStats> {
+        let mut state = lock(&self.shared.state);
+        while !state.done {
+            state = self
+                .shared
+                .finished
+                .wait(state)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+        match state.panic.take() {
+            Some(payload) => Err(payload),
+            None => Ok(state.stats),
+        }
+    }
+}
+
+/// A fixed-size thread pool which preempts jobs exceeding their IR time slice.
+///
+/// The pool waits for all queued jobs to complete when it is dropped.
+pub struct ThreadPool {
+    shared: Arc<Shared>,
+    workers: Vec<JoinHandle<()>>,
+    pid: u32,
+}
+
+impl ThreadPool {
+    /// Creates a thread pool.
+    ///
+    /// This function takes a number of workers and a time slice in IR instructions.
+    /// Each worker registers a Compiler Interrupts handler with the slice as
+    /// both the IR and cycles interval. The pool has at least one worker.
+    pub fn new(threads: usize, slice: i64) -> io::Result<ThreadPool> {
+        let threads = threads.max(1);
+        let shared = Arc::new(Shared {
+            queues: (0..threads).map(|_| Mutex::default()).collect(),
+            next_queue: AtomicUsize::new(0),
+            shutdown: Mutex::new(false),
+            available: Condvar::new(),
+        });
+        let mut pool = ThreadPool {
+            shared,
+            workers: Vec::with_capacity(threads),
+            pid: std::process::id(),
+        };
+        for id in 0..threads {
+            let shared = Arc::clone(&pool.shared);
+            let worker = thread::Builder::new()
+                .name(format!("ci-pool{}", id))
+                .spawn(move || worker(&shared, id, slice))?;
+            pool.workers.push(worker);
+        }
+
+        Ok(pool)
+    }
+
+    /// Spawns a job on the pool.
+    ///
+    /// The job is called once per slice until it returns [`Step::Done`].
+    pub fn spawn<F>(&self, job: F) -> JobHandle
+    where
+        F: FnMut() -> Step + Send + 'static,
+    {
+        let shared = Arc::new(JobShared::default());
+        let task = Task {
+            job: Box::new(job),
+            shared: Arc::clone(&shared),
+        };
+        let queues = self.shared.queues.len();
+        let queue = self.shared.next_queue.fetch_add(1, Ordering::Relaxed) % queues;
+        self.shared.push(queue, task);
+
+        JobHandle { shared }
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        // the workers do not exist in a forked child
+        if self.pid != std::process::id() {
+            std::mem::forget(std::mem::take(&mut self.workers));
+            return;
+        }
+        *lock(&self.shared.shutdown) = true;
+        self.shared.available.notify_all();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Takes the next job, blocking until one is available or the pool shuts down.
+fn next(shared: &Shared, queue: usize) -> Option<Task> {
+    if let Some(task) = shared.pop(queue) {
+        return Some(task);
+    }
+    let mut shutdown = lock(&shared.shutdown);
+    loop {
+        if let Some(task) = shared.pop(queue) {
+            return Some(task);
+        }
+        if *shutdown {
+            return None;
+        }
+        shutdown = shared
+            .available
+            .wait(shutdown)
+            .unwrap_or_else(|e| e.into_inner());
+    }
+}
+
+/// Runs the jobs of the pool until it shuts down.
+fn worker(shared: &Shared, id: usize, slice: i64) {
+    unsafe {
+        crate::register(slice, slice, slice_handler);
+    }
+
+    loop {
+        // the bookkeeping of the pool is not part of any slice
+        unsafe {
+            crate::disable();
+        }
+        let task = next(shared, id);
+        unsafe {
+            // every slice starts with a full IR interval
+            slice_expired = false;
+            slice_ir = 0;
+            crate::restart_ir_counter();
+            crate::enable();
+        }
+
+        let mut task = match task {
+            Some(task) => task,
+            None => break,
+        };
+        let result = panic::catch_unwind(AssertUnwindSafe(&mut task.job));
+
+        unsafe {
+            crate::disable();
+        }
+        let requeue = {
+            let mut state = lock(&task.shared.state);
+            state.stats.slices += 1;
+            state.stats.ir += unsafe { slice_ir };
+            match result {
+                Ok(Step::Yield) => {
+                    state.stats.yields += 1;
+                    true
+                }
+                Ok(Step::Done) => {
+                    state.done = true;
+                    false
+                }
+                Err(payload) => {
+                    state.done = true;
+                    state.panic = Some(payload);
+                    false
+                }
+            }
+        };
+        if requeue {
+            shared.push(id, task);
+        } else {
+            task.shared.finished.notify_all();
+        }
+        unsafe {
+            crate::enable();
+        }
+    }
+
+    unsafe {
+        crate::deregister();
+    }
+}
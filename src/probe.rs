@@ -0,0 +1,177 @@
+//! Probe instrumentation gated by [`instr_enable`](crate::instr_enable)
+//! and [`instr_disable`](crate::instr_disable).
+//!
+//! A probe is a named point of the program which records the IR instructions counted by the
+//! interrupts and a timestamp every time it is hit, while the probe instrumentation is
+//! enabled on the thread. The records are buffered per thread until [`drain`] is called.
+//!
+//! The protocol for instrumented code and user probes is:
+//!
+//! - `instr_enable` and `instr_disable` set and clear the `ci_instr_enabled`
+//!   thread-local flag of the framework. The flag is cleared for new threads.
+//!   Both functions use the C ABI and are declared in the `include/compiler_interrupts.h`
+//!   header.
+//! - A probe checks the flag, which is a single thread-local load, and does nothing
+//!   if it is cleared. Otherwise it records its name, the IR instructions counted since
+//!   the handler was registered as of the last interrupt, and the [`clock`]
+//!   cycle counter.
+//! - Rust code inserts probes with [`Probe::hit`], and C code with
+//!   [`ci_probe`](crate::ffi::ci_probe). Probes can be hit inside interrupt handlers.
+//!
+//! # Examples
+//!
+//! ```
+//! use compiler_interrupts::probe::{self, Probe};
+//! use compiler_interrupts::sim;
+//!
+//! static PARSE: Probe = Probe::new("parse");
+//!
+//! fn interrupt_handler(_ic: i64) {}
+//!
+//! unsafe {
+//!     compiler_interrupts::register(1000, 1000, interrupt_handler);
+//! }
+//!
+//! // not recorded
+//! PARSE.hit();
+//!
+//! probe::enable();
+//! sim::execute(5000);
+//! PARSE.hit();
+//! probe::disable();
+//!
+//! let records = probe::drain();
+//! assert_eq!(records.len(), 1);
+//! assert_eq!(records[0].name, "parse");
+//! assert_eq!(records[0].total_ir, 5000);
+//! ```
+
+use std::cell::RefCell;
+
+use crate::clock;
+
+/// A record of a probe hit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ProbeRecord {
+    /// Name of the probe.
+    pub name: &'static str,
+    /// Approximate number of IR instructions since the handler was registered,
+    /// as counted at the last interrupt.
+    pub total_ir: i64,
+    /// Cycle counter when the probe was hit, measured by [`clock::now`].
+    pub cycles: u64,
+}
+
+thread_local! {
+    /// Records of the current thread.
+    static RECORDS: RefCell<Vec<ProbeRecord>> = const { RefCell::new(Vec::new()) };
+}
+
+/// A named probe.
+///
+/// # Examples
+///
+/// ```
+/// use compiler_interrupts::probe::Probe;
+///
+/// fn checkout() {
+///     static CHECKOUT: Probe = Probe::new("checkout");
+///     CHECKOUT.hit();
+///     // ...
+/// }
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Probe {
+    name: &'static str,
+}
+
+impl Probe {
+    /// Creates a probe with the given name.
+    pub const fn new(name: &'static str) -> Self {
+        Probe { name }
+    }
+
+    /// Returns the name of the probe.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Records a hit of the probe if the probe instrumentation is enabled.
+    ///
+    /// # Note
+    ///
+    /// This function is thread-specific, which means it only records the hit
+    /// on the thread it is called on.
+    #[inline]
+    pub fn hit(&self) {
+        if is_enabled() {
+            record(self.name);
+        }
+    }
+}
+
+/// Records a hit of a probe.
+#[cold]
+pub(crate) fn record(name: &'static str) {
+    let record = ProbeRecord {
+        name,
+        total_ir: unsafe { crate::context::total_ir },
+        cycles: clock::now(),
+    };
+    let _ = RECORDS.try_with(|records| {
+        if let Ok(mut records) = records.try_borrow_mut() {
+            records.push(record);
+        }
+    });
+}
+
+/// Enables the probe instrumentation.
+///
+/// See [`instr_enable`](crate::instr_enable).
+///
+/// # Note
+///
+/// This function is thread-specific, which means it only enables
+/// on the thread it is called on.
+pub fn enable() {
+    unsafe { crate::instr_enable() }
+}
+
+/// Disables the probe instrumentation.
+///
+/// See [`instr_disable`](crate::instr_disable).
+///
+/// # Note
+///
+/// This function is thread-specific, which means it only disables
+/// on the thread it is called on.
+pub fn disable() {
+    unsafe { crate::instr_disable() }
+}
+
+/// Returns `true` if the probe instrumentation is enabled.
+///
+/// # Note
+///
+/// This function is thread-specific, which means it only checks
+/// the thread it is called on.
+#[inline]
+pub fn is_enabled() -> bool {
+    unsafe { crate::ci_instr_enabled }
+}
+
+/// Returns and clears the records of the current thread.
+///
+/// The records are kept until they are drained, hence long-running threads
+/// should drain them periodically.
+///
+/// # Note
+///
+/// This function is thread-specific, which means it only drains the records
+/// of the thread it is called on.
+pub fn drain() -> Vec<ProbeRecord> {
+    RECORDS
+        .try_with(|records| std::mem::take(&mut *records.borrow_mut()))
+        .unwrap_or_default()
+}
@@ -0,0 +1,215 @@
+use std::cell::{Cell, RefCell};
+use std::time::Duration;
+
+use crate::clock;
+
+/// An interrupt passed to the handler of [`RateLimited`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct LimitedFire {
+    /// Interrupts skipped since the last call to the handler.
+    pub skipped: u64,
+    /// IR instructions accumulated since the last call to the handler,
+    /// including the skipped interrupts.
+    pub ir: i64,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Limit {
+    Count(u64),
+    Cycles(u64),
+}
+
+/// A handler adapter which only calls the handler for some of the interrupts.
+///
+/// The adapter filters the interrupts of the thread either by count, calling the handler
+/// every Kth interrupt, or by time, calling the handler at most once per period.
+/// The handler receives the number of skipped interrupts and the IR instructions
+/// accumulated since its last call, hence the intervals of the thread do not
+/// have to be changed for a slower handler.
+///
+/// # Examples
+///
+/// ```
+/// use std::cell::Cell;
+/// use std::rc::Rc;
+///
+/// use compiler_interrupts::{sim, LimitedFire, RateLimited};
+///
+/// let calls = Rc::new(Cell::new(0));
+/// let counter = calls.clone();
+/// let handler = RateLimited::every(10, move |fire: &LimitedFire| {
+///     assert_eq!(fire.skipped, 9);
+///     assert_eq!(fire.ir, 10000);
+///     counter.set(counter.get() + 1);
+/// });
+///
+/// unsafe {
+///     handler.register(1000, 1000);
+/// }
+///
+/// // calls the handler once for 10 interrupts
+/// sim::execute(10000);
+/// assert_eq!(calls.get(), 1);
+/// ```
+pub struct RateLimited<H> {
+    handler: H,
+    limit: Limit,
+    skipped: u64,
+    ir: i64,
+    last: Option<u64>,
+}
+
+pub(crate) type Handler = Box<dyn FnMut(i64)>;
+
+thread_local! {
+    /// Rate-limited handler from [`RateLimited::register`].
+    static LIMITED: RefCell<Option<Handler>> = const { RefCell::new(None) };
+    /// Incremented whenever the rate-limited handler is replaced or cleared.
+    static GENERATION: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Takes the rate-limited handler of the thread.
+pub(crate) fn take() -> Option<Handler> {
+    let _ = GENERATION.try_with(|generation| generation.set(generation.get() + 1));
+    LIMITED
+        .try_with(|limited| limited.borrow_mut().take())
+        .ok()
+        .flatten()
+}
+
+/// Replaces the rate-limited handler of the thread.
+pub(crate) fn restore(handler: Option<Handler>) {
+    let _ = GENERATION.try_with(|generation| generation.set(generation.get() + 1));
+    // the previous handler is dropped after releasing the borrow
+    let _previous =
+        LIMITED.try_with(|limited| std::mem::replace(&mut *limited.borrow_mut(), handler));
+}
+
+/// Drops the rate-limited handler of the thread, when another handler is registered
+/// or the handler is de-registered.
+pub(crate) fn clear() {
+    drop(take());
+}
+
+/// A running rate-limited handler, which is put back when it returns or panics
+/// unless the handler of the thread has been replaced meanwhile.
+struct Running {
+    handler: Option<Handler>,
+    generation: u64,
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        if GENERATION.try_with(Cell::get) == Ok(self.generation) {
+            let handler = self.handler.take();
+            let _ = LIMITED.try_with(|limited| *limited.borrow_mut() = handler);
+        }
+    }
+}
+
+/// Dispatches an interrupt to the rate-limited handler of the thread.
+fn limited_dispatch(ic: i64) {
+    // the handler is taken out, so the borrow is released while it runs
+    let handler = LIMITED.try_with(|limited| limited.borrow_mut().take());
+    if let Ok(Some(handler)) = handler {
+        let mut running = Running {
+            handler: Some(handler),
+            generation: GENERATION.try_with(Cell::get).unwrap_or_default(),
+        };
+        if let Some(handler) = &mut running.handler {
+            handler(ic);
+        }
+    }
+}
+
+impl<H> RateLimited<H>
+where
+    H: FnMut(&LimitedFire),
+{
+    /// Creates an adapter calling the handler every `k` interrupts.
+    pub fn every(k: u64, handler: H) -> Self {
+        Self::new(Limit::Count(k.max(1)), handler)
+    }
+
+    /// Creates an adapter calling the handler at most once per period.
+    ///
+    /// The handler is called by the first interrupt, then by the first interrupt
+    /// after the period has elapsed since the last call.
+    pub fn at_most_every(period: Duration, handler: H) -> Self {
+        Self::new(Limit::Cycles(clock::from_duration(period)), handler)
+    }
+
+    fn new(limit: Limit, handler: H) -> Self {
+        RateLimited {
+            handler,
+            limit,
+            skipped: 0,
+            ir: 0,
+            last: None,
+        }
+    }
+
+    /// Records an interrupt, and calls the handler if the limit allows it.
+    ///
+    /// This function takes the IR instructions since the last interrupt, like the
+    /// handlers of [`register`](crate::register). It is called by the handler from
+    /// [`register`](RateLimited::register), and can be called from other handlers
+    /// to combine the adapter with them.
+    pub fn fire(&mut self, ic: i64) {
+        self.ir += ic;
+        let due = match self.limit {
+            Limit::Count(k) => self.skipped + 1 >= k,
+            Limit::Cycles(period) => {
+                let now = clock::now();
+                match self.last {
+                    Some(last) if now.wrapping_sub(last) < period => false,
+                    _ => {
+                        self.last = Some(now);
+                        true
+                    }
+                }
+            }
+        };
+        if !due {
+            self.skipped += 1;
+            return;
+        }
+
+        let fire = LimitedFire {
+            skipped: self.skipped,
+            ir: self.ir,
+        };
+        self.skipped = 0;
+        self.ir = 0;
+        (self.handler)(&fire);
+    }
+}
+
+impl<H> RateLimited<H>
+where
+    H: FnMut(&LimitedFire) + 'static,
+{
+    /// Registers the adapter as the handler for Compiler Interrupts.
+    ///
+    /// This function takes a IR interval and cycles interval like [`register`](crate::register),
+    /// and replaces the previous handler of the thread.
+    ///
+    /// # Note
+    ///
+    /// This function is thread-specific, which means it only registers
+    /// on the thread they called on.
+    ///
+    /// The adapter is dropped when the handler is de-registered or replaced by
+    /// another registration. A panic of the handler does not drop it.
+    ///
+    /// # Safety
+    ///
+    /// This function mutates a thread-local static variable which uses for the interrupt handler.
+    /// Thread unsafety will not be introduced. Rust considers mutating static variable unsafe.
+    pub unsafe fn register(mut self, ir_interval: i64, cycles_interval: i64) {
+        // the registration clears the previous rate-limited handler
+        crate::register(ir_interval, cycles_interval, limited_dispatch);
+        restore(Some(Box::new(move |ic| self.fire(ic))));
+    }
+}
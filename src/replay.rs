@@ -0,0 +1,380 @@
+//! Deterministic record and replay of interrupts.
+//!
+//! In record mode, the interrupt function of the framework logs the IR instructions
+//! accumulated since the start of the recording at every interrupt of the thread.
+//! In replay mode, the IR interval of the thread is set to the distance to the next
+//! recorded interrupt after every interrupt, hence a program which executes the same
+//! IR instructions, for example with the [`sim`](crate::sim) driver, fires its
+//! interrupts at the same points.
+//!
+//! Logs are stored in a compact binary format with [`LogWriter`] and [`LogReader`].
+//! A file starts with the magic bytes `CIRL` and a version byte, followed by the threads.
+//! Every thread is stored as its name and number of interrupts, followed by the distance
+//! between consecutive interrupts, all encoded as LEB128 variable-length integers.
+//!
+//! # Examples
+//!
+//! ```
+//! use std::cell::RefCell;
+//!
+//! use compiler_interrupts::replay::{self, LogReader, LogWriter};
+//! use compiler_interrupts::{sim, Fire, InterruptContext};
+//!
+//! fn interrupt_handler(ctx: &mut InterruptContext) {
+//!     // interrupts land at irregular points
+//!     let next = 1000 + ctx.fire().total_ir % 777;
+//!     ctx.set_next_interval(next, next);
+//! }
+//!
+//! unsafe {
+//!     compiler_interrupts::register_with_context(1000, 1000, interrupt_handler);
+//! }
+//! replay::record();
+//! sim::execute(100_000);
+//! let recorded = replay::stop();
+//!
+//! let mut file = Vec::new();
+//! let mut writer = LogWriter::new(&mut file).unwrap();
+//! writer.write(&recorded).unwrap();
+//! writer.finish().unwrap();
+//!
+//! let mut reader = LogReader::new(&file[..]).unwrap();
+//! let log = reader.next().unwrap().unwrap();
+//! assert_eq!(log, recorded);
+//!
+//! // the handler no longer decides where the interrupts land
+//! thread_local! {
+//!     static FIRES: RefCell<Vec<u64>> = RefCell::new(Vec::new());
+//! }
+//!
+//! fn fire_handler(fire: &Fire) {
+//!     FIRES.with(|fires| fires.borrow_mut().push(fire.total_ir as u64));
+//! }
+//!
+//! unsafe {
+//!     compiler_interrupts::register_with_fire(1000, 1000, fire_handler);
+//! }
+//! replay::replay(&log);
+//! sim::execute(100_000);
+//! replay::stop();
+//!
+//! assert_eq!(FIRES.with(|fires| fires.take()), log.fires);
+//! ```
+
+use std::cell::RefCell;
+use std::io::{self, Read, Write};
+
+/// Magic bytes of the log format.
+const MAGIC: &[u8; 4] = b"CIRL";
+
+/// Version of the log format.
+const VERSION: u8 = 1;
+
+/// Mode of the current thread.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Off,
+    Record,
+    Replay,
+}
+
+#[allow(non_upper_case_globals)]
+#[thread_local]
+static mut mode: Mode = Mode::Off;
+
+/// Recording or replay state of a thread.
+struct State {
+    /// IR instructions accumulated since the start.
+    ir: u64,
+    fires: Vec<u64>,
+    /// Index of the next interrupt to replay.
+    next: usize,
+    /// Intervals of the thread before the replay.
+    intervals: (i64, i64, i64, i64),
+}
+
+thread_local! {
+    static STATE: RefCell<State> = const {
+        RefCell::new(State {
+            ir: 0,
+            fires: Vec::new(),
+            next: 0,
+            intervals: (0, 0, 0, 0),
+        })
+    };
+}
+
+/// Interrupts of a thread.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ThreadLog {
+    /// Name of the thread, empty if the thread is unnamed.
+    pub name: String,
+    /// IR instructions accumulated since the start of the recording at every interrupt.
+    pub fires: Vec<u64>,
+}
+
+/// Starts recording the interrupts of the current thread.
+///
+/// The recording discards the previous recording or replay of the thread.
+///
+/// # Note
+///
+/// This function is thread-specific, which means it only records the interrupts
+/// of the thread it is called on.
+pub fn record() {
+    stop();
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        state.ir = 0;
+        state.fires.clear();
+    });
+    unsafe {
+        mode = Mode::Record;
+    }
+}
+
+/// Starts replaying the interrupts of a log on the current thread.
+///
+/// The IR interval of the thread is set to the first recorded interrupt, and the
+/// [`sim`](crate::sim) counter of the thread is reset. Once all interrupts are replayed,
+/// the IR interval is set so that no further interrupts fire until [`stop`].
+/// A handler must have been registered on the thread.
+///
+/// # Note
+///
+/// This function is thread-specific, which means it only replays the interrupts
+/// on the thread it is called on.
+pub fn replay(log: &ThreadLog) {
+    stop();
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        state.ir = 0;
+        state.fires.clear();
+        state.fires.extend_from_slice(&log.fires);
+        state.next = 0;
+        unsafe {
+            state.intervals = (
+                crate::ci_ir_interval,
+                crate::ci_reset_ir_interval,
+                crate::ci_cycles_interval,
+                crate::ci_cycles_threshold,
+            );
+            // only the recorded interrupts fire
+            crate::ci_cycles_interval = i64::MAX;
+            crate::ci_cycles_threshold = i64::MAX;
+            set_next(&state);
+            mode = Mode::Replay;
+        }
+    });
+    crate::sim::reset();
+}
+
+/// Stops recording or replaying on the current thread.
+///
+/// Returns the recorded interrupts, which are empty if the thread was not recording.
+/// After a replay, the intervals of the thread are restored.
+///
+/// # Note
+///
+/// This function is thread-specific, which means it only stops
+/// on the thread it is called on.
+pub fn stop() -> ThreadLog {
+    ThreadLog {
+        name: std::thread::current().name().unwrap_or_default().into(),
+        fires: finish(),
+    }
+}
+
+/// Stops recording or replaying on the forking thread in a forked child process.
+pub(crate) fn fork_child() {
+    finish();
+}
+
+/// Stops recording or replaying, and returns the recorded interrupts.
+fn finish() -> Vec<u64> {
+    let previous = unsafe { mode };
+    unsafe {
+        mode = Mode::Off;
+    }
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        match previous {
+            Mode::Off => Vec::new(),
+            Mode::Record => std::mem::take(&mut state.fires),
+            Mode::Replay => {
+                unsafe {
+                    let (ir, reset, cycles, threshold) = state.intervals;
+                    crate::ci_ir_interval = ir;
+                    crate::ci_reset_ir_interval = reset;
+                    crate::ci_cycles_interval = cycles;
+                    crate::ci_cycles_threshold = threshold;
+                }
+                state.fires.clear();
+                Vec::new()
+            }
+        }
+    })
+}
+
+/// Sets the IR interval to the next interrupt to replay.
+unsafe fn set_next(state: &State) {
+    let interval = match state.fires.get(state.next) {
+        Some(&fire) => fire.saturating_sub(state.ir).clamp(1, i64::MAX as u64) as i64,
+        None => i64::MAX,
+    };
+    crate::ci_ir_interval = interval;
+    crate::ci_reset_ir_interval = interval;
+}
+
+/// Records or replays an interrupt.
+#[inline]
+pub(crate) fn after_fire(ic: i64) {
+    if unsafe { mode } == Mode::Off {
+        return;
+    }
+    let _ = STATE.try_with(|state| {
+        let mut state = state.borrow_mut();
+        state.ir += ic.max(0) as u64;
+        unsafe {
+            match mode {
+                Mode::Record => {
+                    let ir = state.ir;
+                    state.fires.push(ir);
+                }
+                Mode::Replay => {
+                    state.next += 1;
+                    set_next(&state);
+                }
+                Mode::Off => {}
+            }
+        }
+    });
+}
+
+fn write_varint<W: Write>(writer: &mut W, mut value: u64) -> io::Result<()> {
+    let mut buf = [0; 10];
+    let mut len = 0;
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            buf[len] = byte;
+            len += 1;
+            break;
+        }
+        buf[len] = byte | 0x80;
+        len += 1;
+    }
+    writer.write_all(&buf[..len])
+}
+
+fn read_varint<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0];
+        reader.read_exact(&mut byte)?;
+        value |= u64::from(byte[0] & 0x7f) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(invalid("varint is too long"))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// A writer of interrupt logs.
+pub struct LogWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> LogWriter<W> {
+    /// Creates a writer and writes the header of the log.
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[VERSION])?;
+        Ok(LogWriter { writer })
+    }
+
+    /// Writes the interrupts of a thread.
+    pub fn write(&mut self, log: &ThreadLog) -> io::Result<()> {
+        write_varint(&mut self.writer, log.name.len() as u64)?;
+        self.writer.write_all(log.name.as_bytes())?;
+        write_varint(&mut self.writer, log.fires.len() as u64)?;
+        let mut prev = 0;
+        for &fire in &log.fires {
+            write_varint(&mut self.writer, fire.wrapping_sub(prev))?;
+            prev = fire;
+        }
+        Ok(())
+    }
+
+    /// Flushes the log and returns the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// A reader of interrupt logs.
+///
+/// The reader is an iterator over the threads of the log.
+pub struct LogReader<R: Read> {
+    reader: R,
+}
+
+impl<R: Read> LogReader<R> {
+    /// Creates a reader and checks the header of the log.
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut header = [0; 5];
+        reader.read_exact(&mut header)?;
+        if &header[..4] != MAGIC {
+            return Err(invalid("not an interrupt log"));
+        }
+        if header[4] != VERSION {
+            return Err(invalid("unsupported interrupt log version"));
+        }
+        Ok(LogReader { reader })
+    }
+
+    fn read_thread(&mut self, name_len: u64) -> io::Result<ThreadLog> {
+        let mut name = Vec::new();
+        (&mut self.reader).take(name_len).read_to_end(&mut name)?;
+        if name.len() as u64 != name_len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let name = String::from_utf8(name).map_err(|_| invalid("thread name is not UTF-8"))?;
+
+        let len = read_varint(&mut self.reader)?;
+        let mut fires = Vec::new();
+        let mut fire = 0u64;
+        for _ in 0..len {
+            fire = fire.wrapping_add(read_varint(&mut self.reader)?);
+            fires.push(fire);
+        }
+
+        Ok(ThreadLog { name, fires })
+    }
+}
+
+impl<R: Read> Iterator for LogReader<R> {
+    type Item = io::Result<ThreadLog>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // the log ends cleanly between threads
+        let mut byte = [0];
+        match self.reader.read(&mut byte) {
+            Ok(0) => return None,
+            Ok(_) => {}
+            Err(error) => return Some(Err(error)),
+        }
+        let name_len = if byte[0] & 0x80 == 0 {
+            Ok(u64::from(byte[0]))
+        } else {
+            read_varint(&mut self.reader).map(|rest| u64::from(byte[0] & 0x7f) | rest << 7)
+        };
+        Some(name_len.and_then(|name_len| self.read_thread(name_len)))
+    }
+}
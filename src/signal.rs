@@ -0,0 +1,208 @@
+//! Interoperability with POSIX signal handlers.
+//!
+//! An interrupt handler which fires inside a signal handler runs in async-signal context,
+//! where most operations such as allocating or taking locks are illegal. The installers
+//! of this module wrap signal handlers with [`defer`], which disables Compiler Interrupts
+//! for the duration of the handler and restores the previous state on exit. Signal
+//! handlers installed by other means can call [`defer`] themselves.
+//!
+//! Unlike [`disable`](crate::disable) and [`enable`](crate::enable), the wrappers
+//! do not call the enable and disable hooks, which are not async-signal-safe.
+//!
+//! This module is available on Unix platforms.
+//!
+//! # Examples
+//!
+//! ```
+//! use std::sync::atomic::{AtomicBool, Ordering};
+//!
+//! use compiler_interrupts::signal;
+//!
+//! static HANDLED: AtomicBool = AtomicBool::new(false);
+//!
+//! fn handler(_signum: libc::c_int) {
+//!     assert!(signal::in_signal_handler());
+//!     HANDLED.store(true, Ordering::Relaxed);
+//! }
+//!
+//! unsafe {
+//!     signal::install(libc::SIGUSR1, handler).expect("failed to install handler");
+//!     libc::raise(libc::SIGUSR1);
+//! }
+//! assert!(HANDLED.load(Ordering::Relaxed));
+//! assert!(!signal::in_signal_handler());
+//! ```
+
+use std::convert::TryFrom;
+use std::ffi::c_void;
+use std::io;
+use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use libc::{c_int, siginfo_t};
+
+/// Handler of a signal from [`install`].
+pub type Handler = fn(c_int);
+
+/// Handler of a signal with its information from [`install_with_info`].
+pub type InfoHandler = fn(c_int, &siginfo_t, *mut c_void);
+
+/// Number of signals supported by the installers.
+const MAX_SIGNALS: usize = 65;
+
+/// Handlers from [`install`], indexed by signal number.
+static HANDLERS: [AtomicUsize; MAX_SIGNALS] = [const { AtomicUsize::new(0) }; MAX_SIGNALS];
+
+/// Handlers from [`install_with_info`], indexed by signal number.
+static INFO_HANDLERS: [AtomicUsize; MAX_SIGNALS] = [const { AtomicUsize::new(0) }; MAX_SIGNALS];
+
+/// Nesting depth of the signal handlers running on the current thread.
+#[allow(non_upper_case_globals)]
+#[thread_local]
+static mut signal_depth: u32 = 0;
+
+/// Returns `true` if the current thread is running a signal handler wrapped by [`defer`].
+///
+/// # Note
+///
+/// This function is thread-specific, which means it only checks
+/// the thread it is called on.
+pub fn in_signal_handler() -> bool {
+    unsafe { signal_depth > 0 }
+}
+
+/// Runs a signal handler with Compiler Interrupts disabled.
+///
+/// This function increments the disable depth and disables the interrupts before calling
+/// the closure, then restores the previous depth and interrupt function. It does not call
+/// the enable and disable hooks, and it is async-signal-safe as long as the closure is.
+///
+/// # Note
+///
+/// This function is thread-specific, which means it only disables the interrupts
+/// on the thread it is called on.
+///
+/// # Examples
+///
+/// ```
+/// use compiler_interrupts::signal;
+///
+/// extern "C" fn handler(_signum: libc::c_int) {
+///     signal::defer(|| {
+///         // no interrupts fire here
+///     });
+/// }
+/// ```
+pub fn defer<R, F: FnOnce() -> R>(f: F) -> R {
+    /// Restores the state even if the handler unwinds.
+    struct Restore {
+        depth: i32,
+        hook: fn(i64),
+    }
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            unsafe {
+                crate::lc_disabled_count = self.depth;
+                crate::intvActionHook = self.hook;
+                signal_depth -= 1;
+            }
+        }
+    }
+
+    let _restore = unsafe {
+        let restore = Restore {
+            depth: crate::lc_disabled_count,
+            hook: crate::intvActionHook,
+        };
+        signal_depth += 1;
+        crate::intvActionHook = crate::dummy;
+        crate::lc_disabled_count += 1;
+        restore
+    };
+    f()
+}
+
+extern "C" fn dispatch(signum: c_int) {
+    let handler = HANDLERS[signum as usize].load(Ordering::Acquire);
+    if handler != 0 {
+        let handler: Handler = unsafe { mem::transmute(handler) };
+        defer(|| handler(signum));
+    }
+}
+
+extern "C" fn dispatch_with_info(signum: c_int, info: *mut siginfo_t, context: *mut c_void) {
+    let handler = INFO_HANDLERS[signum as usize].load(Ordering::Acquire);
+    if handler != 0 {
+        let handler: InfoHandler = unsafe { mem::transmute(handler) };
+        defer(|| handler(signum, unsafe { &*info }, context));
+    }
+}
+
+/// Stores the handler and installs the dispatch function with `sigaction`.
+unsafe fn sigaction(
+    handlers: &[AtomicUsize; MAX_SIGNALS],
+    signum: c_int,
+    handler: usize,
+    action: usize,
+    flags: c_int,
+) -> io::Result<()> {
+    let slot = usize::try_from(signum)
+        .ok()
+        .and_then(|i| handlers.get(i))
+        .ok_or_else(|| io::Error::from_raw_os_error(libc::EINVAL))?;
+    slot.store(handler, Ordering::Release);
+
+    let mut sa: libc::sigaction = mem::zeroed();
+    sa.sa_sigaction = action;
+    sa.sa_flags = flags;
+    libc::sigemptyset(&mut sa.sa_mask);
+    if libc::sigaction(signum, &sa, std::ptr::null_mut()) != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Installs a signal handler which runs with Compiler Interrupts disabled.
+///
+/// This function takes a signal number and a handler, and installs the handler
+/// with `sigaction` and `SA_RESTART`. The handler is wrapped by [`defer`].
+///
+/// # Safety
+///
+/// The handler runs in async-signal context, hence it must only call
+/// async-signal-safe functions. Installing a handler replaces the previous
+/// disposition of the signal, including handlers installed by other code.
+pub unsafe fn install(signum: c_int, handler: Handler) -> io::Result<()> {
+    let action: extern "C" fn(c_int) = dispatch;
+    sigaction(
+        &HANDLERS,
+        signum,
+        handler as usize,
+        action as usize,
+        libc::SA_RESTART,
+    )
+}
+
+/// Installs a signal handler receiving the signal information
+/// which runs with Compiler Interrupts disabled.
+///
+/// This function takes a signal number and a handler, and installs the handler
+/// with `sigaction`, `SA_SIGINFO` and `SA_RESTART`. The handler is wrapped by [`defer`].
+///
+/// # Safety
+///
+/// The handler runs in async-signal context, hence it must only call
+/// async-signal-safe functions. Installing a handler replaces the previous
+/// disposition of the signal, including handlers installed by other code.
+pub unsafe fn install_with_info(signum: c_int, handler: InfoHandler) -> io::Result<()> {
+    let action: extern "C" fn(c_int, *mut siginfo_t, *mut c_void) = dispatch_with_info;
+    sigaction(
+        &INFO_HANDLERS,
+        signum,
+        handler as usize,
+        action as usize,
+        libc::SA_SIGINFO | libc::SA_RESTART,
+    )
+}
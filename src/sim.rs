@@ -0,0 +1,110 @@
+//! Simulated driver for Compiler Interrupts.
+//!
+//! Programs which are not compiled with the Compiler Interrupts pass never call the
+//! interrupt function of the framework. This module drives the interrupt function the way
+//! the instrumented code would, which is useful for testing handlers and for benchmarks.
+//!
+//! The driver only simulates the IR interval. Code reports the IR instructions it has
+//! executed with [`execute`], and the interrupt function is called every time
+//! the IR interval from [`register`](crate::register) elapses. While the interrupts are
+//! disabled, elapsed intervals are dropped, like with the instrumented code.
+//!
+//! Programs which are only sometimes built with the pass, such as the examples of the crate,
+//! can report their loops with [`tick`], which only drives the interrupts if the
+//! `CI_SIMULATE` environment variable is set.
+//!
+//! # Examples
+//!
+//! ```
+//! use compiler_interrupts::sim;
+//!
+//! fn interrupt_handler(ic: i64) {
+//!     assert_eq!(ic, 1000);
+//! }
+//!
+//! unsafe {
+//!     compiler_interrupts::register(1000, 1000, interrupt_handler);
+//! }
+//!
+//! // fires the handler 10 times
+//! for _ in 0..100 {
+//!     sim::execute(100);
+//! }
+//! ```
+
+use std::env;
+use std::sync::OnceLock;
+
+/// Environment variable enabling [`tick`].
+pub const ENV_VAR: &str = "CI_SIMULATE";
+
+/// IR instructions executed since the last interrupt.
+#[allow(non_upper_case_globals)]
+#[thread_local]
+static mut executed: i64 = 0;
+
+/// Calls the interrupt function of the framework.
+///
+/// This function behaves like a probe of the instrumented code which has just
+/// counted the given number of IR instructions.
+///
+/// # Note
+///
+/// This function is thread-specific, which means it only fires the interrupt
+/// on the thread it is called on.
+#[inline]
+pub fn fire(ic: i64) {
+    unsafe { crate::intvActionHook(ic) }
+}
+
+/// Executes IR instructions.
+///
+/// This function advances the simulated IR counter of the current thread, and calls the
+/// interrupt function of the framework every time the IR interval elapses.
+///
+/// # Note
+///
+/// This function is thread-specific, which means it only advances the counter
+/// of the thread it is called on.
+pub fn execute(ir: i64) {
+    unsafe {
+        executed += ir;
+        loop {
+            let interval = crate::ci_ir_interval.max(1);
+            if executed < interval {
+                break;
+            }
+            executed -= interval;
+            fire(interval);
+        }
+    }
+}
+
+/// Executes IR instructions if the `CI_SIMULATE` environment variable is set.
+///
+/// This function behaves like [`execute`] if the variable is set when it is first called,
+/// and does nothing otherwise.
+///
+/// # Note
+///
+/// This function is thread-specific, which means it only advances the counter
+/// of the thread it is called on.
+#[inline]
+pub fn tick(ir: i64) {
+    static ENABLED: OnceLock<bool> = OnceLock::new();
+    if *ENABLED.get_or_init(|| env::var_os(ENV_VAR).is_some()) {
+        execute(ir);
+    }
+}
+
+/// Resets the simulated IR counter of the current thread.
+///
+/// # Note
+///
+/// This function is thread-specific, which means it only resets the counter
+/// of the thread it is called on.
+pub fn reset() {
+    unsafe {
+        executed = 0;
+    }
+}
@@ -0,0 +1,355 @@
+//! Periodic and one-shot timers driven by Compiler Interrupts.
+//!
+//! Every thread keeps a min-heap of its timers, keyed on the deadline in cycles of
+//! the [`clock`]. The interrupt function of the framework checks the heap
+//! after the handler from [`register`](crate::register) returns, and calls the timers
+//! which are due. Timers are therefore as precise as the interrupt intervals, and only
+//! fire on threads with a registered handler while the interrupts are enabled.
+//!
+//! Each timer records how late it fired compared to its deadline in [`TimerStats`].
+//!
+//! Adding a timer allocates, hence timers cannot be added inside an interrupt handler,
+//! including the callbacks of other timers. Cancelling a timer does not allocate.
+//!
+//! # Examples
+//!
+//! ```
+//! use std::time::Duration;
+//!
+//! use compiler_interrupts::{sim, timers};
+//!
+//! fn interrupt_handler(_ic: i64) {}
+//!
+//! unsafe {
+//!     compiler_interrupts::register(1000, 1000, interrupt_handler);
+//! }
+//!
+//! let heartbeat = timers::add_timer(Duration::from_micros(100), || {
+//!     println!("still alive");
+//! });
+//!
+//! // the first interrupt after the period calls the timer
+//! std::thread::sleep(Duration::from_micros(200));
+//! sim::execute(1000);
+//! assert_eq!(heartbeat.stats().fires, 1);
+//!
+//! heartbeat.cancel();
+//! assert!(heartbeat.is_finished());
+//! ```
+
+use std::cell::{Cell, RefCell};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::rc::Rc;
+use std::time::Duration;
+
+use crate::clock;
+
+/// Deadline of the earliest timer of the current thread, in cycles.
+#[allow(non_upper_case_globals)]
+#[thread_local]
+static mut next_deadline: u64 = u64::MAX;
+
+/// Lateness statistics of a timer.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct TimerStats {
+    /// Number of times the timer fired.
+    pub fires: u64,
+    /// Lateness of the last firing.
+    pub last_lateness: Duration,
+    /// Maximum lateness.
+    pub max_lateness: Duration,
+    /// Sum of the lateness of all firings.
+    pub total_lateness: Duration,
+}
+
+impl TimerStats {
+    /// Returns the average lateness.
+    pub fn mean_lateness(&self) -> Duration {
+        if self.fires == 0 {
+            Duration::ZERO
+        } else {
+            Duration::from_nanos((self.total_lateness.as_nanos() / self.fires as u128) as u64)
+        }
+    }
+
+    fn record(&mut self, lateness: Duration) {
+        self.fires += 1;
+        self.last_lateness = lateness;
+        self.max_lateness = self.max_lateness.max(lateness);
+        self.total_lateness += lateness;
+    }
+}
+
+/// State shared between a timer and its handle.
+#[derive(Default)]
+struct Shared {
+    stats: Cell<TimerStats>,
+    cancelled: Cell<bool>,
+}
+
+enum Callback {
+    Periodic(Box<dyn FnMut()>),
+    Once(Box<dyn FnOnce()>),
+}
+
+struct Timer {
+    deadline: u64,
+    period: u64,
+    callback: Callback,
+    shared: Rc<Shared>,
+}
+
+#[derive(Default)]
+struct Timers {
+    next_id: u64,
+    heap: BinaryHeap<Reverse<(u64, u64)>>,
+    timers: HashMap<u64, Timer>,
+}
+
+impl Timers {
+    fn update_deadline(&mut self) {
+        // skip the heap entries of cancelled timers
+        while let Some(&Reverse((_, id))) = self.heap.peek() {
+            if self.timers.contains_key(&id) {
+                break;
+            }
+            self.heap.pop();
+        }
+        unsafe {
+            next_deadline = self
+                .heap
+                .peek()
+                .map_or(u64::MAX, |&Reverse((deadline, _))| deadline);
+        }
+    }
+}
+
+thread_local! {
+    static TIMERS: RefCell<Timers> = RefCell::new(Timers::default());
+}
+
+/// A handle to a timer from [`add_timer`] or [`add_oneshot`].
+///
+/// Dropping the handle does not cancel the timer.
+#[derive(Clone)]
+pub struct TimerHandle {
+    id: u64,
+    shared: Rc<Shared>,
+}
+
+impl TimerHandle {
+    /// Cancels the timer.
+    ///
+    /// A timer which cancels itself still finishes its current call.
+    pub fn cancel(&self) {
+        self.shared.cancelled.set(true);
+        // the timer is dropped after releasing the borrow
+        let _timer = TIMERS.try_with(|timers| {
+            let mut timers = timers.borrow_mut();
+            let timer = timers.timers.remove(&self.id);
+            timers.update_deadline();
+            timer
+        });
+    }
+
+    /// Returns `true` if the timer was cancelled, or if it was a one-shot timer which fired.
+    pub fn is_finished(&self) -> bool {
+        self.shared.cancelled.get()
+    }
+
+    /// Returns the lateness statistics of the timer.
+    pub fn stats(&self) -> TimerStats {
+        self.shared.stats.get()
+    }
+}
+
+fn add(delay: Duration, period: Duration, callback: Callback) -> TimerHandle {
+    debug_assert!(
+        !crate::in_interrupt(),
+        "timers cannot be added inside an interrupt handler"
+    );
+    let shared = Rc::new(Shared::default());
+    let deadline = clock::now().saturating_add(clock::from_duration(delay));
+    let timer = Timer {
+        deadline,
+        period: clock::from_duration(period).max(1),
+        callback,
+        shared: Rc::clone(&shared),
+    };
+    let id = TIMERS.with(|timers| {
+        let mut timers = timers.borrow_mut();
+        let id = timers.next_id;
+        timers.next_id += 1;
+        timers.heap.push(Reverse((deadline, id)));
+        timers.timers.insert(id, timer);
+        timers.update_deadline();
+        id
+    });
+
+    TimerHandle { id, shared }
+}
+
+/// Adds a periodic timer.
+///
+/// This function takes a period and a closure, which is called by the interrupts of
+/// the current thread about every period. If the interrupts are late by more than
+/// a period, the missed calls are skipped rather than run in a burst.
+///
+/// # Note
+///
+/// This function is thread-specific, which means the timer only fires
+/// on the thread it is called on.
+///
+/// The closure runs inside the interrupt handler, see [`in_interrupt`](crate::in_interrupt).
+///
+/// This function cannot be called inside an interrupt handler, since it allocates.
+pub fn add_timer<F>(period: Duration, callback: F) -> TimerHandle
+where
+    F: FnMut() + 'static,
+{
+    add(period, period, Callback::Periodic(Box::new(callback)))
+}
+
+/// Adds a one-shot timer.
+///
+/// This function takes a delay and a closure, which is called once by the first
+/// interrupt of the current thread after the delay.
+///
+/// # Note
+///
+/// This function is thread-specific, which means the timer only fires
+/// on the thread it is called on.
+///
+/// The closure runs inside the interrupt handler, see [`in_interrupt`](crate::in_interrupt).
+///
+/// This function cannot be called inside an interrupt handler, since it allocates.
+///
+/// # Examples
+///
+/// ```
+/// use std::cell::Cell;
+/// use std::rc::Rc;
+/// use std::time::Duration;
+///
+/// use compiler_interrupts::{sim, timers};
+///
+/// fn interrupt_handler(_ic: i64) {}
+///
+/// unsafe {
+///     compiler_interrupts::register(1000, 1000, interrupt_handler);
+/// }
+///
+/// let fired = Rc::new(Cell::new(false));
+/// let flag = fired.clone();
+/// let timer = timers::add_oneshot(Duration::ZERO, move || flag.set(true));
+///
+/// sim::execute(1000);
+/// assert!(fired.get());
+/// assert_eq!(timer.stats().fires, 1);
+/// ```
+pub fn add_oneshot<F>(delay: Duration, callback: F) -> TimerHandle
+where
+    F: FnOnce() + 'static,
+{
+    add(delay, Duration::ZERO, Callback::Once(Box::new(callback)))
+}
+
+/// Cancels the timers of the forking thread in a forked child process.
+///
+/// The callbacks were created by the parent, so they are leaked instead of dropped.
+pub(crate) fn fork_child() {
+    let _ = TIMERS.try_with(|timers| {
+        if let Ok(mut timers) = timers.try_borrow_mut() {
+            let pending = std::mem::take(&mut timers.timers);
+            for timer in pending.values() {
+                timer.shared.cancelled.set(true);
+            }
+            std::mem::forget(pending);
+            timers.heap.clear();
+        }
+    });
+    unsafe {
+        next_deadline = u64::MAX;
+    }
+}
+
+/// Calls the timers of the current thread which are due.
+#[inline]
+pub(crate) fn poll() {
+    if unsafe { next_deadline } == u64::MAX {
+        return;
+    }
+    let now = clock::now();
+    if now < unsafe { next_deadline } {
+        return;
+    }
+    fire_due(now);
+}
+
+/// Next entry of the heap.
+enum Next {
+    Due(u64, Timer),
+    Cancelled,
+    Done,
+}
+
+#[cold]
+fn fire_due(now: u64) {
+    loop {
+        let next = TIMERS
+            .try_with(|timers| {
+                let mut timers = timers.borrow_mut();
+                match timers.heap.peek() {
+                    Some(&Reverse((deadline, id))) if deadline <= now => {
+                        timers.heap.pop();
+                        match timers.timers.remove(&id) {
+                            Some(timer) => Next::Due(id, timer),
+                            None => Next::Cancelled,
+                        }
+                    }
+                    _ => Next::Done,
+                }
+            })
+            .unwrap_or(Next::Done);
+        let (id, mut timer) = match next {
+            Next::Due(id, timer) => (id, timer),
+            Next::Cancelled => continue,
+            Next::Done => break,
+        };
+
+        let lateness = clock::to_duration(now - timer.deadline);
+        let mut stats = timer.shared.stats.get();
+        stats.record(lateness);
+        timer.shared.stats.set(stats);
+
+        let callback = match timer.callback {
+            Callback::Periodic(mut callback) => {
+                callback();
+                callback
+            }
+            Callback::Once(callback) => {
+                timer.shared.cancelled.set(true);
+                callback();
+                continue;
+            }
+        };
+        if timer.shared.cancelled.get() {
+            continue;
+        }
+
+        timer.callback = Callback::Periodic(callback);
+        timer.deadline = timer.deadline.saturating_add(timer.period);
+        if timer.deadline <= now {
+            timer.deadline = now.saturating_add(timer.period);
+        }
+        let _ = TIMERS.try_with(|timers| {
+            let mut timers = timers.borrow_mut();
+            timers.heap.push(Reverse((timer.deadline, id)));
+            timers.timers.insert(id, timer);
+        });
+    }
+
+    let _ = TIMERS.try_with(|timers| timers.borrow_mut().update_deadline());
+}
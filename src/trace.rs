@@ -0,0 +1,202 @@
+//! Integration with [`tracing`] for interrupt handlers.
+//!
+//! Emitting `tracing` events from an interrupt handler is unsafe in practice, since the
+//! subscriber may take locks at an arbitrary instrumentation point. Inside the handlers,
+//! [`event`] stores the events in a fixed-size per-thread buffer without allocating. The
+//! buffered events are emitted outside interrupt context by [`flush`], which is also called
+//! when leaving a critical section with [`enable`](crate::enable) and by
+//! [`deregister`](crate::deregister).
+//!
+//! The crate also emits a `ci_disabled` span for every critical section between the
+//! outermost [`disable`](crate::disable) and [`enable`](crate::enable), and a `ci_registered`
+//! span from every registration of a handler until its de-registration. A handler
+//! de-registered inside an interrupt handler leaves its span at the next [`flush`].
+//!
+//! This module is available with the `tracing` feature.
+//!
+//! # Examples
+//!
+//! ```
+//! use compiler_interrupts::trace;
+//! use tracing::Level;
+//!
+//! fn interrupt_handler(ic: i64) {
+//!     trace::event(Level::INFO, "interrupt fired", ic);
+//! }
+//!
+//! unsafe {
+//!     compiler_interrupts::register(10000, 10000, interrupt_handler);
+//! }
+//!
+//! // ...
+//!
+//! // emits the buffered events
+//! trace::flush();
+//! ```
+
+use std::cell::RefCell;
+use std::ptr::addr_of_mut;
+
+use tracing::span::EnteredSpan;
+use tracing::Level;
+
+/// Capacity of the per-thread event buffer.
+pub const CAPACITY: usize = 64;
+
+/// An event emitted inside an interrupt handler.
+#[derive(Clone, Copy)]
+struct Event {
+    level: Level,
+    message: &'static str,
+    value: i64,
+}
+
+/// Fixed-size buffer of the events of a thread.
+struct Buffer {
+    events: [Event; CAPACITY],
+    len: usize,
+    dropped: u64,
+}
+
+/// Buffered events of the current thread.
+#[allow(non_upper_case_globals)]
+#[thread_local]
+static mut buffer: Buffer = Buffer {
+    events: [Event {
+        level: Level::TRACE,
+        message: "",
+        value: 0,
+    }; CAPACITY],
+    len: 0,
+    dropped: 0,
+};
+
+thread_local! {
+    /// Span of the current critical section.
+    static SECTION: RefCell<Option<EnteredSpan>> = const { RefCell::new(None) };
+    /// Span of the current registration.
+    static REGISTRATION: RefCell<Option<EnteredSpan>> = const { RefCell::new(None) };
+}
+
+/// Emits an event, buffering it if the current thread is inside an interrupt handler.
+///
+/// Inside a handler, the event is stored in the per-thread buffer without allocating, and
+/// emitted by the next [`flush`]. If the buffer is full, the event is dropped and counted.
+/// Outside a handler, the buffered events and this event are emitted immediately.
+///
+/// # Note
+///
+/// This function is thread-specific, which means it only buffers the event
+/// on the thread it is called on.
+pub fn event(level: Level, message: &'static str, value: i64) {
+    let event = Event {
+        level,
+        message,
+        value,
+    };
+    if !crate::in_interrupt() {
+        flush();
+        emit(&event);
+        return;
+    }
+
+    let buf = unsafe { &mut *addr_of_mut!(buffer) };
+    if buf.len < CAPACITY {
+        buf.events[buf.len] = event;
+        buf.len += 1;
+    } else {
+        buf.dropped += 1;
+    }
+}
+
+/// Emits the events buffered by the interrupt handlers of the current thread.
+///
+/// Calling this function inside an interrupt handler does nothing.
+///
+/// # Note
+///
+/// This function is thread-specific, which means it only emits the events
+/// of the thread it is called on.
+pub fn flush() {
+    if crate::in_interrupt() {
+        return;
+    }
+
+    let buf = unsafe { &mut *addr_of_mut!(buffer) };
+    let (events, len, dropped) = (buf.events, buf.len, buf.dropped);
+    buf.len = 0;
+    buf.dropped = 0;
+
+    for event in &events[..len] {
+        emit(event);
+    }
+    if dropped > 0 {
+        tracing::warn!(dropped, "compiler interrupts event buffer overflowed");
+    }
+
+    // leaves the span of a handler de-registered inside an interrupt handler
+    if unsafe { !crate::registered } {
+        let _ = REGISTRATION.try_with(|registration| registration.borrow_mut().take());
+    }
+}
+
+/// Emits an event to the `tracing` subscriber.
+fn emit(event: &Event) {
+    let (message, value) = (event.message, event.value);
+    match event.level {
+        Level::ERROR => tracing::error!(value, "{}", message),
+        Level::WARN => tracing::warn!(value, "{}", message),
+        Level::INFO => tracing::info!(value, "{}", message),
+        Level::DEBUG => tracing::debug!(value, "{}", message),
+        _ => tracing::trace!(value, "{}", message),
+    }
+}
+
+/// Enters the span of a critical section.
+pub(crate) fn enter_section() {
+    if crate::in_interrupt() {
+        return;
+    }
+    let span = tracing::trace_span!("ci_disabled").entered();
+    let _ = SECTION.try_with(|section| *section.borrow_mut() = Some(span));
+}
+
+/// Exits the span of a critical section and flushes the buffered events.
+pub(crate) fn leave_section() {
+    if crate::in_interrupt() {
+        return;
+    }
+    let _ = SECTION.try_with(|section| section.borrow_mut().take());
+    flush();
+}
+
+/// Enters the span of a registration, leaving the span of the previous registration.
+pub(crate) fn registered(ir_interval: i64, cycles_interval: i64) {
+    if crate::in_interrupt() {
+        return;
+    }
+    let _ = REGISTRATION.try_with(|registration| {
+        let mut registration = registration.borrow_mut();
+        registration.take();
+        let span = tracing::debug_span!("ci_registered", ir_interval, cycles_interval).entered();
+        tracing::debug!("compiler interrupts registered");
+        *registration = Some(span);
+    });
+}
+
+/// Flushes the buffered events and leaves the span of a de-registration.
+pub(crate) fn deregistered() {
+    if crate::in_interrupt() {
+        return;
+    }
+    // the buffered events are emitted inside the span of the registration
+    let span = REGISTRATION
+        .try_with(|registration| registration.borrow_mut().take())
+        .ok()
+        .flatten();
+    flush();
+    if let Some(span) = span {
+        tracing::debug!("compiler interrupts de-registered");
+        span.exit();
+    }
+}
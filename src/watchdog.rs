@@ -0,0 +1,317 @@
+//! Watchdog for threads which stopped reaching interrupt points.
+//!
+//! A thread which spins in uninstrumented code, such as FFI calls, inline assembly or
+//! blocking system calls, never fires its interrupt handler. Threads opt in with [`watch`],
+//! after which every interrupt records a timestamp for the thread. A [`Watchdog`] monitor
+//! thread reports the watched threads that have been silent for longer than its threshold.
+//!
+//! # Examples
+//!
+//! ```
+//! use std::time::Duration;
+//! use compiler_interrupts::watchdog::{self, Watchdog};
+//!
+//! let watchdog = Watchdog::spawn(Duration::from_millis(100), |stall| {
+//!     eprintln!(
+//!         "thread {:?} silent for {:?} at disable depth {}",
+//!         stall.name, stall.silent_for, stall.disable_depth
+//!     );
+//! })
+//! .expect("failed to spawn watchdog");
+//!
+//! // opt in the current thread
+//! watchdog::watch();
+//!
+//! for stall in watchdog.stalled() {
+//!     println!("{:?} is stalled", stall.thread);
+//! }
+//! ```
+
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::io;
+use std::ptr;
+use std::ptr::addr_of_mut;
+use std::sync::atomic::{AtomicI32, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, OnceLock};
+use std::thread::{self, JoinHandle, ThreadId};
+use std::time::{Duration, Instant};
+
+/// Watched threads of the process.
+static REGISTRY: Mutex<Vec<Arc<Heartbeat>>> = Mutex::new(Vec::new());
+
+/// Heartbeat of the current thread if it is watched.
+#[allow(non_upper_case_globals)]
+#[thread_local]
+static mut heartbeat: *const Heartbeat = ptr::null();
+
+/// Registry locked by the forking thread.
+#[allow(non_upper_case_globals)]
+#[thread_local]
+static mut forking: Option<MutexGuard<'static, Vec<Arc<Heartbeat>>>> = None;
+
+thread_local! {
+    /// Removes the current thread from the registry when it exits.
+    static WATCHED: RefCell<Option<Watched>> = const { RefCell::new(None) };
+}
+
+/// Per-thread state shared with the monitor thread.
+#[derive(Debug)]
+struct Heartbeat {
+    thread: ThreadId,
+    name: Option<String>,
+    last: AtomicU64,
+    depth: AtomicI32,
+}
+
+/// Registry entry of the current thread.
+struct Watched(Arc<Heartbeat>);
+
+impl Drop for Watched {
+    fn drop(&mut self) {
+        unsafe {
+            heartbeat = ptr::null();
+        }
+        let mut registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+        registry.retain(|hb| !Arc::ptr_eq(hb, &self.0));
+    }
+}
+
+/// Returns the nanoseconds elapsed since the first use of the watchdog.
+fn now() -> u64 {
+    static ORIGIN: OnceLock<Instant> = OnceLock::new();
+    ORIGIN.get_or_init(Instant::now).elapsed().as_nanos() as u64
+}
+
+/// Watches the current thread.
+///
+/// Every interrupt on the current thread records a timestamp which
+/// is checked by the running [`Watchdog`] monitors.
+///
+/// # Note
+///
+/// This function is thread-specific, which means it only watches
+/// the thread it is called on. The thread stops being watched when it exits.
+///
+/// This function can be called multiple times.
+/// Consecutive calls will do nothing as the thread is already watched.
+pub fn watch() {
+    #[cfg(unix)]
+    crate::fork::install();
+    WATCHED.with(|watched| {
+        let mut watched = watched.borrow_mut();
+        if watched.is_some() {
+            return;
+        }
+
+        let thread = thread::current();
+        let hb = Arc::new(Heartbeat {
+            thread: thread.id(),
+            name: thread.name().map(String::from),
+            last: AtomicU64::new(now()),
+            depth: AtomicI32::new(unsafe { crate::lc_disabled_count }),
+        });
+        unsafe {
+            heartbeat = Arc::as_ptr(&hb);
+        }
+        REGISTRY
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(Arc::clone(&hb));
+        *watched = Some(Watched(hb));
+    });
+}
+
+/// Stops watching the current thread.
+///
+/// This function removes the current thread from [`watch`].
+///
+/// # Note
+///
+/// This function is thread-specific, which means it only stops watching
+/// the thread it is called on.
+///
+/// This function can be called multiple times.
+/// Consecutive calls will do nothing as the thread is no longer watched.
+pub fn unwatch() {
+    WATCHED.with(|watched| watched.borrow_mut().take());
+}
+
+/// Records an interrupt on the current thread.
+///
+/// # Safety
+///
+/// This function reads a thread-local static variable which uses for the heartbeat.
+pub(crate) unsafe fn beat() {
+    if !heartbeat.is_null() {
+        (*heartbeat).last.store(now(), Ordering::Relaxed);
+    }
+}
+
+/// Records the disable depth of the current thread.
+///
+/// # Safety
+///
+/// This function reads a thread-local static variable which uses for the heartbeat.
+pub(crate) unsafe fn set_depth(depth: i32) {
+    if !heartbeat.is_null() {
+        (*heartbeat).depth.store(depth, Ordering::Relaxed);
+    }
+}
+
+/// A watched thread which has not reached an interrupt point for a while.
+#[derive(Clone, Debug)]
+pub struct Stall {
+    /// Identifier of the thread.
+    pub thread: ThreadId,
+    /// Name of the thread, if any.
+    pub name: Option<String>,
+    /// Time since the last interrupt, or since [`watch`] if no interrupt has fired yet.
+    pub silent_for: Duration,
+    /// Number of outstanding [`disable`](crate::disable) calls on the thread.
+    pub disable_depth: i32,
+}
+
+/// State shared between a [`Watchdog`] and its monitor thread.
+#[derive(Default)]
+struct Shared {
+    stopped: Mutex<bool>,
+    wakeup: Condvar,
+    stalled: Mutex<Vec<Stall>>,
+}
+
+/// A monitor thread which reports stalled threads.
+///
+/// The monitor stops when the watchdog is dropped.
+pub struct Watchdog {
+    shared: Arc<Shared>,
+    monitor: Option<JoinHandle<()>>,
+    pid: u32,
+}
+
+impl Watchdog {
+    /// Spawns a monitor thread.
+    ///
+    /// This function takes a threshold and a callback. The monitor periodically checks
+    /// the threads from [`watch`] and calls the callback once for every thread
+    /// which becomes silent for longer than the threshold.
+    pub fn spawn<F>(threshold: Duration, callback: F) -> io::Result<Watchdog>
+    where
+        F: FnMut(&Stall) + Send + 'static,
+    {
+        #[cfg(unix)]
+        crate::fork::install();
+        let shared = Arc::new(Shared::default());
+        let monitor = {
+            let shared = Arc::clone(&shared);
+            thread::Builder::new()
+                .name("ci-watchdog".into())
+                .spawn(move || monitor(&shared, threshold, callback))?
+        };
+
+        Ok(Watchdog {
+            shared,
+            monitor: Some(monitor),
+            pid: std::process::id(),
+        })
+    }
+
+    /// Returns the threads which were stalled at the last check.
+    pub fn stalled(&self) -> Vec<Stall> {
+        self.shared
+            .stalled
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        // the monitor thread does not exist in a forked child
+        if self.pid != std::process::id() {
+            std::mem::forget(self.monitor.take());
+            return;
+        }
+        *self
+            .shared
+            .stopped
+            .lock()
+            .unwrap_or_else(|e| e.into_inner()) = true;
+        self.shared.wakeup.notify_one();
+        if let Some(monitor) = self.monitor.take() {
+            let _ = monitor.join();
+        }
+    }
+}
+
+/// Runs the monitor loop until the watchdog is dropped.
+fn monitor<F: FnMut(&Stall)>(shared: &Shared, threshold: Duration, mut callback: F) {
+    let period = (threshold / 4).max(Duration::from_millis(1));
+    let limit = threshold.as_nanos() as u64;
+    let mut reported = HashSet::new();
+
+    let mut stopped = shared.stopped.lock().unwrap_or_else(|e| e.into_inner());
+    while !*stopped {
+        let now = now();
+        let stalled: Vec<Stall> = REGISTRY
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .filter_map(|hb| {
+                let silent = now.saturating_sub(hb.last.load(Ordering::Relaxed));
+                if silent <= limit {
+                    return None;
+                }
+                Some(Stall {
+                    thread: hb.thread,
+                    name: hb.name.clone(),
+                    silent_for: Duration::from_nanos(silent),
+                    disable_depth: hb.depth.load(Ordering::Relaxed),
+                })
+            })
+            .collect();
+
+        // the stalled threads are visible to `stalled` before the callback runs
+        *shared.stalled.lock().unwrap_or_else(|e| e.into_inner()) = stalled.clone();
+
+        // only report the threads which have just stalled
+        reported.retain(|thread| stalled.iter().any(|stall| stall.thread == *thread));
+        for stall in &stalled {
+            if reported.insert(stall.thread) {
+                callback(stall);
+            }
+        }
+
+        stopped = shared
+            .wakeup
+            .wait_timeout(stopped, period)
+            .unwrap_or_else(|e| e.into_inner())
+            .0;
+    }
+}
+
+/// Locks the registry before forking.
+pub(crate) fn fork_prepare() {
+    let registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+    unsafe {
+        forking = Some(registry);
+    }
+}
+
+/// Unlocks the registry in the parent process.
+pub(crate) fn fork_parent() {
+    unsafe {
+        (*addr_of_mut!(forking)).take();
+    }
+}
+
+/// Removes the other threads from the registry and unlocks it in the child process.
+pub(crate) fn fork_child() {
+    unsafe {
+        if let Some(mut registry) = (*addr_of_mut!(forking)).take() {
+            let current = heartbeat;
+            registry.retain(|hb| ptr::eq(Arc::as_ptr(hb), current));
+        }
+    }
+}
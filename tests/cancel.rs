@@ -0,0 +1,82 @@
+//! Checks the cancellation of computations at interrupt points with the simulated driver.
+
+use std::ffi::c_void;
+
+use compiler_interrupts::{sim, with_cancellation, CancellationToken, Cancelled, DisableGuard};
+
+fn interrupt_handler(_ic: i64) {}
+
+extern "C" fn c_interrupt_handler(_ic: i64, _ctx: *mut c_void) {}
+
+#[test]
+fn unwinds_at_interrupt_point() {
+    unsafe {
+        compiler_interrupts::register(1000, 1000, interrupt_handler);
+    }
+
+    let token = CancellationToken::new();
+    let mut executed = 0;
+    let result = with_cancellation(&token, || {
+        for _ in 0..100 {
+            sim::execute(500);
+            executed += 500;
+            if executed == 2500 {
+                token.cancel();
+            }
+        }
+    });
+
+    // unwinds at the next interrupt after the cancellation
+    assert_eq!(result, Err(Cancelled));
+    assert_eq!(executed, 2500);
+
+    unsafe {
+        compiler_interrupts::deregister();
+    }
+}
+
+#[test]
+fn completes_disabled_section() {
+    unsafe {
+        compiler_interrupts::register(1000, 1000, interrupt_handler);
+    }
+
+    let token = CancellationToken::new();
+    let mut completed = false;
+    let result = with_cancellation(&token, || {
+        {
+            let _guard = unsafe { DisableGuard::new() };
+            token.cancel();
+            sim::execute(10000);
+            completed = true;
+        }
+        sim::execute(1000);
+        unreachable!("the computation must unwind after the disabled section");
+    });
+
+    assert_eq!(result, Err(Cancelled));
+    assert!(completed);
+
+    unsafe {
+        compiler_interrupts::deregister();
+    }
+}
+
+#[test]
+fn skips_c_handlers() {
+    unsafe {
+        compiler_interrupts::register_ctx(1000, 1000, c_interrupt_handler, std::ptr::null_mut());
+    }
+
+    let token = CancellationToken::new();
+    let result = with_cancellation(&token, || {
+        token.cancel();
+        sim::execute(10000);
+        42
+    });
+    assert_eq!(result, Ok(42));
+
+    unsafe {
+        compiler_interrupts::deregister();
+    }
+}
@@ -0,0 +1,91 @@
+//! Checks the intervals of the chaos mode with the simulated driver.
+
+use std::cell::RefCell;
+use std::sync::Mutex;
+
+use compiler_interrupts::chaos::{self, Config, Distribution};
+use compiler_interrupts::{sim, InterruptContext};
+
+/// Serializes the tests, since the chaos mode applies to all threads.
+static LOCK: Mutex<()> = Mutex::new(());
+
+thread_local! {
+    static INTERVALS: RefCell<Vec<i64>> = const { RefCell::new(Vec::new()) };
+}
+
+fn interrupt_handler(ic: i64) {
+    INTERVALS.with(|intervals| intervals.borrow_mut().push(ic));
+}
+
+fn context_handler(ctx: &mut InterruptContext) {
+    let first = INTERVALS.with(|intervals| {
+        let mut intervals = intervals.borrow_mut();
+        intervals.push(ctx.ir());
+        intervals.len() == 1
+    });
+    if first {
+        ctx.set_next_interval(5000, 5000);
+    }
+}
+
+/// Returns the intervals of the interrupts fired while executing the given IR instructions.
+fn intervals(ir: i64) -> Vec<i64> {
+    sim::execute(ir);
+    INTERVALS.with(|intervals| intervals.borrow_mut().split_off(0))
+}
+
+/// Runs the chaos mode with a seed and returns the intervals of the interrupts.
+fn run(seed: u64, distribution: Distribution) -> Vec<i64> {
+    Config::chaos(seed).distribution(distribution).install();
+    unsafe {
+        compiler_interrupts::register(1000, 1000, interrupt_handler);
+    }
+    let intervals = intervals(200_000);
+    unsafe {
+        compiler_interrupts::deregister();
+    }
+    chaos::uninstall();
+    sim::reset();
+    intervals
+}
+
+#[test]
+fn same_seed_same_intervals() {
+    let _lock = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+    for distribution in [
+        Distribution::Uniform,
+        Distribution::Exponential,
+        Distribution::Burst,
+    ] {
+        let first = run(42, distribution);
+        assert!(first.len() > 10);
+        assert!(first.iter().any(|&ic| ic != 1000), "{:?}", distribution);
+        assert_eq!(first, run(42, distribution), "{:?}", distribution);
+        assert_ne!(first, run(43, distribution), "{:?}", distribution);
+    }
+}
+
+#[test]
+fn randomizes_intervals_from_handler() {
+    let _lock = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+    Config::chaos(42).install();
+    unsafe {
+        compiler_interrupts::register_with_context(1000, 1000, context_handler);
+    }
+    let randomized = intervals(500_000);
+    assert_eq!(randomized[0], 1000);
+    let average = randomized[1..].iter().sum::<i64>() / (randomized.len() - 1) as i64;
+    assert!((2500..7500).contains(&average), "average {}", average);
+
+    // the intervals from the handler are restored instead of the intervals from `register`
+    chaos::uninstall();
+    let restored = intervals(50_000);
+    assert!(restored[1..].iter().all(|&ic| ic == 5000), "{:?}", restored);
+
+    unsafe {
+        compiler_interrupts::deregister();
+    }
+    sim::reset();
+}
@@ -0,0 +1,138 @@
+//! Checks the behavior of the examples.
+//!
+//! If [`cargo-compiler-interrupts`][cargo-compiler-interrupts] is installed, the examples are
+//! built with the Compiler Interrupts pass. Otherwise, they are built normally and run with
+//! the `CI_SIMULATE` environment variable, so that their loops drive the simulated driver.
+//! The output of the examples is checked the same way in both cases.
+//!
+//! [cargo-compiler-interrupts]: https://github.com/bitslab/cargo-compiler-interrupts
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use compiler_interrupts::sim;
+
+/// Returns `true` if the `cargo run-ci` subcommand is installed.
+fn has_ci_toolchain() -> bool {
+    let path = match env::var_os("PATH") {
+        Some(path) => path,
+        None => return false,
+    };
+    env::split_paths(&path).any(|dir| Path::new(&dir).join("cargo-run-ci").is_file())
+}
+
+/// Runs an example in a directory and returns its output.
+///
+/// The example is built with the Compiler Interrupts pass if it is installed,
+/// or runs on the simulated driver otherwise.
+fn run_example(example: &str, args: &[&str], dir: &Path) -> String {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let mut command = Command::new(env::var("CARGO").unwrap_or_else(|_| "cargo".into()));
+    command.current_dir(dir);
+    if has_ci_toolchain() {
+        command
+            .env("CARGO_TARGET_DIR", root.join("target").join("ci"))
+            .arg("run-ci");
+    } else {
+        eprintln!("cargo-compiler-interrupts is not installed, using the simulated driver");
+        command.env(sim::ENV_VAR, "1").arg("run");
+    }
+    let output = command
+        .arg("--manifest-path")
+        .arg(root.join("Cargo.toml"))
+        .args(["--example", example, "--"])
+        .args(args)
+        .output()
+        .expect("failed to run cargo");
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    assert!(
+        output.status.success(),
+        "{} failed:\n{}\n{}",
+        example,
+        stdout,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    stdout
+}
+
+/// Returns the intervals reported by the interrupt handler of `demo` for a thread prefix.
+fn demo_intervals<'a>(stdout: &'a str, prefix: &'a str) -> impl Iterator<Item = i64> + 'a {
+    stdout.lines().filter_map(move |line| {
+        let rest = line.strip_prefix("CI @ ")?;
+        if !rest.starts_with(prefix) {
+            return None;
+        }
+        let interval = rest.split("last interval = ").nth(1)?;
+        interval.trim_end_matches(" IR").parse().ok()
+    })
+}
+
+#[test]
+fn demo() {
+    let stdout = run_example("demo", &["2"], Path::new(env!("CARGO_MANIFEST_DIR")));
+    for prefix in &["inc", "dec"] {
+        let intervals: Vec<i64> = demo_intervals(&stdout, prefix).collect();
+        assert!(
+            !intervals.is_empty(),
+            "interrupts did not fire on {} threads",
+            prefix
+        );
+        assert!(intervals.iter().all(|&ic| ic >= 0), "negative interval");
+    }
+
+    // each decrement thread disables twice and enables twice
+    let disables = stdout.matches("called before CI is disabled").count();
+    let enables = stdout.matches("called after CI is enabled").count();
+    assert_eq!(disables, 2 * 2);
+    assert_eq!(enables, 2 * 2);
+
+    for i in 0..2 {
+        let thread = format!("dec{}", i);
+        let prefix = format!("CI @ {}:", thread);
+
+        // no interrupts between the outermost disable and enable of a decrement thread
+        let disabled = format!("called before CI is disabled on {}", thread);
+        let enabled = format!("called after CI is enabled on {}", thread);
+        let start = stdout.find(&disabled).expect("disable hook was not called");
+        let end = stdout.rfind(&enabled).expect("enable hook was not called");
+        assert!(
+            !stdout[start..end].contains(&prefix),
+            "{} fired while disabled",
+            thread
+        );
+
+        // no interrupts after a decrement thread de-registers and prints its counter
+        let done = format!("decrement(): thread: {} ", thread);
+        let pos = stdout.find(&done).expect("decrement thread did not finish");
+        assert!(
+            !stdout[pos..].contains(&prefix),
+            "{} fired after deregister",
+            thread
+        );
+    }
+}
+
+#[test]
+fn profiler() {
+    // the intervals are logged to files in the working directory
+    let dir = env::temp_dir().join(format!("ci-profiler-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let stdout = run_example("profiler", &["1000000"], &dir);
+
+    if cfg!(target_os = "linux") {
+        for thread in &["inc0", "inc1"] {
+            assert!(
+                stdout.contains(&format!("thread: {} -> median interval", thread)),
+                "interrupts did not fire on {}:\n{}",
+                thread,
+                stdout
+            );
+            let log = fs::read_to_string(dir.join(format!("{}_intervals.txt", thread)))
+                .expect("intervals were not logged on exit");
+            assert!(log.starts_with("percentile, time-stamp counter, instruction count"));
+        }
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
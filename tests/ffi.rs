@@ -0,0 +1,59 @@
+//! Checks the hooks of the C ABI alongside the hooks of Rust code.
+
+use std::cell::Cell;
+
+use compiler_interrupts::ffi;
+
+thread_local! {
+    static C_HOOKS: Cell<u32> = const { Cell::new(0) };
+    static RUST_HOOKS: Cell<u32> = const { Cell::new(0) };
+}
+
+extern "C" fn c_hook() {
+    C_HOOKS.with(|calls| calls.set(calls.get() + 1));
+}
+
+fn rust_hook() {
+    RUST_HOOKS.with(|calls| calls.set(calls.get() + 1));
+}
+
+#[test]
+fn c_and_rust_hooks_coexist() {
+    unsafe {
+        compiler_interrupts::register_enable_hook(rust_hook);
+        ffi::ci_register_enable_hook(Some(c_hook));
+        let id = compiler_interrupts::add_enable_hook(|_| rust_hook());
+
+        compiler_interrupts::disable();
+        compiler_interrupts::enable();
+        assert_eq!(C_HOOKS.with(Cell::get), 1);
+        assert_eq!(RUST_HOOKS.with(Cell::get), 2);
+
+        // only removes the C hook
+        ffi::ci_deregister_enable_hook();
+        compiler_interrupts::disable();
+        compiler_interrupts::enable();
+        assert_eq!(C_HOOKS.with(Cell::get), 1);
+        assert_eq!(RUST_HOOKS.with(Cell::get), 4);
+
+        compiler_interrupts::remove_hook(id);
+        compiler_interrupts::deregister_enable_hook();
+    }
+}
+
+#[test]
+fn c_hook_is_replaced() {
+    unsafe {
+        ffi::ci_register_disable_hook(Some(c_hook));
+        ffi::ci_register_disable_hook(Some(c_hook));
+
+        compiler_interrupts::disable();
+        compiler_interrupts::enable();
+        assert_eq!(C_HOOKS.with(Cell::get), 1);
+
+        ffi::ci_register_disable_hook(None);
+        compiler_interrupts::disable();
+        compiler_interrupts::enable();
+        assert_eq!(C_HOOKS.with(Cell::get), 1);
+    }
+}
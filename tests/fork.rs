@@ -0,0 +1,97 @@
+//! Checks the state of Compiler Interrupts in forked child processes.
+
+#![cfg(unix)]
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use compiler_interrupts::fork::{self, ForkPolicy};
+use compiler_interrupts::{replay, sim, timers};
+use nix::sys::wait::{waitpid, WaitStatus};
+use nix::unistd::{fork, ForkResult};
+
+static FIRES: AtomicUsize = AtomicUsize::new(0);
+
+/// Serializes the tests, since the fork policy is process-wide.
+static LOCK: Mutex<()> = Mutex::new(());
+
+fn interrupt_handler(_ic: i64) {
+    FIRES.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Forks the process, and returns the exit code of the child running `f`.
+fn in_child<F: FnOnce() -> i32>(f: F) -> i32 {
+    match unsafe { fork() }.expect("failed to fork") {
+        ForkResult::Child => {
+            let code = f();
+            unsafe { nix::libc::_exit(code) }
+        }
+        ForkResult::Parent { child } => {
+            match waitpid(child, None).expect("failed to wait for child") {
+                WaitStatus::Exited(_, code) => code,
+                status => panic!("child did not exit: {:?}", status),
+            }
+        }
+    }
+}
+
+/// Forks the process, and returns the number of interrupts fired in the child
+/// after executing 10 intervals.
+fn fires_in_child(policy: ForkPolicy) -> i32 {
+    fork::set_policy(policy);
+    unsafe {
+        compiler_interrupts::register(1000, 1000, interrupt_handler);
+    }
+
+    let fires = in_child(|| {
+        FIRES.store(0, Ordering::Relaxed);
+        sim::execute(10 * 1000);
+        FIRES.load(Ordering::Relaxed) as i32
+    });
+    unsafe {
+        compiler_interrupts::deregister();
+    }
+    fires
+}
+
+#[test]
+fn keep_handler() {
+    let _lock = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    assert_eq!(fires_in_child(ForkPolicy::KeepHandler), 10);
+}
+
+#[test]
+fn reset_handler() {
+    let _lock = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    assert_eq!(fires_in_child(ForkPolicy::ResetHandler), 0);
+}
+
+#[test]
+fn stops_timers_and_recording() {
+    let _lock = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    fork::set_policy(ForkPolicy::KeepHandler);
+    unsafe {
+        compiler_interrupts::register(1000, 1000, interrupt_handler);
+    }
+    let timer = timers::add_timer(Duration::ZERO, || unsafe { nix::libc::_exit(1) });
+    replay::record();
+
+    let code = in_child(|| {
+        sim::execute(10 * 1000);
+        if !timer.is_finished() {
+            return 2;
+        }
+        if !replay::stop().fires.is_empty() {
+            return 3;
+        }
+        0
+    });
+    assert_eq!(code, 0);
+
+    replay::stop();
+    timer.cancel();
+    unsafe {
+        compiler_interrupts::deregister();
+    }
+}
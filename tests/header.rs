@@ -0,0 +1,80 @@
+//! Checks that `include/compiler_interrupts.h` matches the C ABI of the `ffi` module.
+//!
+//! If [`cbindgen`][cbindgen] is installed, or its path is set in the `CBINDGEN` environment
+//! variable, the header is regenerated and compared with the checked-in header. Otherwise,
+//! the declarations of the header are compared with the exported functions.
+//!
+//! [cbindgen]: https://github.com/eqrion/cbindgen
+
+use std::collections::BTreeSet;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+fn root() -> &'static Path {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+}
+
+/// Returns the path of `cbindgen` if it is installed.
+fn cbindgen() -> Option<PathBuf> {
+    if let Some(path) = env::var_os("CBINDGEN") {
+        return Some(path.into());
+    }
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join("cbindgen"))
+        .find(|path| path.is_file())
+}
+
+/// Returns the names of the functions exported by the `ffi` module.
+fn exported_functions() -> BTreeSet<String> {
+    let source = fs::read_to_string(root().join("src").join("ffi.rs")).unwrap();
+    source
+        .lines()
+        .filter_map(|line| line.strip_prefix("pub unsafe extern \"C\" fn "))
+        .map(|rest| rest.split('(').next().unwrap().to_string())
+        .collect()
+}
+
+#[test]
+fn header_is_up_to_date() {
+    let header = fs::read_to_string(root().join("include").join("compiler_interrupts.h")).unwrap();
+
+    if let Some(cbindgen) = cbindgen() {
+        let output = Command::new(cbindgen)
+            .current_dir(root())
+            .args(["--config", "cbindgen.toml", "--quiet"])
+            .output()
+            .expect("failed to run cbindgen");
+        assert!(
+            output.status.success(),
+            "cbindgen failed:\n{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            header,
+            "the header is outdated, regenerate it with cbindgen"
+        );
+        return;
+    }
+
+    // every item of the header is a function of the `ffi` module
+    let mut declared = BTreeSet::new();
+    for line in header.lines() {
+        if let Some(name) = line.strip_prefix("#define ") {
+            assert_eq!(
+                name, "COMPILER_INTERRUPTS_H",
+                "unexpected constant in the header"
+            );
+        } else if let Some(rest) = line.strip_prefix("void ") {
+            let name = rest.trim_start_matches('*').split('(').next().unwrap();
+            declared.insert(name.to_string());
+        } else {
+            let item =
+                line.starts_with(|c: char| c.is_ascii_alphabetic()) && !line.starts_with("extern");
+            assert!(!item, "unexpected item in the header: {}", line);
+        }
+    }
+    assert_eq!(declared, exported_functions(), "the header is outdated");
+}
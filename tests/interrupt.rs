@@ -0,0 +1,60 @@
+//! Checks the interrupt context of the handlers with the simulated driver.
+
+use std::cell::Cell;
+use std::panic;
+
+use compiler_interrupts::{in_interrupt, sim, DisableGuard};
+
+thread_local! {
+    static FIRES: Cell<u32> = const { Cell::new(0) };
+}
+
+fn interrupt_handler(_ic: i64) {
+    assert!(in_interrupt());
+    panic!("handler failed");
+}
+
+fn critical_handler(ic: i64) {
+    FIRES.with(|fires| fires.set(fires.get() + 1));
+    unsafe {
+        compiler_interrupts::disable();
+        compiler_interrupts::enable();
+    }
+    {
+        let _guard = unsafe { DisableGuard::new() };
+    }
+
+    // an interrupt point reached by the handler does not fire again
+    sim::fire(ic);
+    assert!(in_interrupt());
+}
+
+#[test]
+fn leaves_interrupt_after_panic() {
+    unsafe {
+        compiler_interrupts::register(1000, 1000, interrupt_handler);
+    }
+
+    let result = panic::catch_unwind(|| sim::execute(1000));
+    assert!(result.is_err());
+    assert!(!in_interrupt());
+
+    unsafe {
+        compiler_interrupts::deregister();
+    }
+}
+
+#[test]
+fn enable_inside_handler_does_not_nest() {
+    unsafe {
+        compiler_interrupts::register(1000, 1000, critical_handler);
+    }
+
+    sim::execute(10_000);
+    assert_eq!(FIRES.with(Cell::get), 10);
+    assert!(!in_interrupt());
+
+    unsafe {
+        compiler_interrupts::deregister();
+    }
+}
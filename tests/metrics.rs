@@ -0,0 +1,36 @@
+//! Checks the metrics and their endpoint.
+#![cfg(feature = "metrics")]
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+
+use compiler_interrupts::metrics;
+
+#[test]
+fn silent_client_does_not_block_endpoint() {
+    let addr = metrics::serve("127.0.0.1:0").unwrap();
+
+    // connects without ever sending a request
+    let _silent = TcpStream::connect(addr).unwrap();
+
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.write_all(b"GET /metrics HTTP/1.1\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK"));
+}
+
+#[test]
+fn registration_guard_counts_deregistration() {
+    fn interrupt_handler(_ic: i64) {}
+
+    let before = metrics::snapshot();
+    {
+        let _guard = unsafe { compiler_interrupts::register_scoped(1000, 1000, interrupt_handler) };
+    }
+    let after = metrics::snapshot();
+
+    // other tests of this file do not register handlers
+    assert_eq!(after.registrations - before.registrations, 1);
+    assert_eq!(after.deregistrations - before.deregistrations, 1);
+}
@@ -0,0 +1,85 @@
+//! Checks the preemption of the thread pool with the simulated driver.
+
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+
+use compiler_interrupts::pool::{self, Step, ThreadPool};
+use compiler_interrupts::sim;
+
+#[test]
+fn short_job_overtakes_yielding_job() {
+    let pool = ThreadPool::new(1, 1000).expect("failed to create thread pool");
+    let finished = Arc::new(Mutex::new(Vec::new()));
+
+    let (spawned, short_spawned) = mpsc::channel();
+    let mut waiting = Some(short_spawned);
+    let mut executed = 0;
+    let slices = Arc::new(Mutex::new(Vec::new()));
+    let order = Arc::clone(&finished);
+    let long_slices = Arc::clone(&slices);
+    let long = pool.spawn(move || {
+        // the short job is queued before the first slice expires
+        if let Some(short_spawned) = waiting.take() {
+            short_spawned.recv().unwrap();
+        }
+        let start = executed;
+        while executed < 100_000 {
+            sim::execute(100);
+            executed += 100;
+            if pool::should_yield() {
+                long_slices.lock().unwrap().push(executed - start);
+                return Step::Yield;
+            }
+        }
+        order.lock().unwrap().push("long");
+        Step::Done
+    });
+
+    let order = Arc::clone(&finished);
+    let short = pool.spawn(move || {
+        sim::execute(500);
+        order.lock().unwrap().push("short");
+        Step::Done
+    });
+    spawned.send(()).unwrap();
+
+    let short = short.join().expect("short job panicked");
+    let long = long.join().expect("long job panicked");
+    assert_eq!(*finished.lock().unwrap(), ["short", "long"]);
+
+    assert_eq!(short.slices, 1);
+    assert_eq!(short.yields, 0);
+    assert_eq!(short.ir, 0);
+
+    // every slice runs for a full interval of the job
+    assert!(slices.lock().unwrap().iter().all(|&ir| ir == 1000));
+    assert_eq!(long.yields, 100);
+    assert_eq!(long.slices, 101);
+    assert_eq!(long.ir, 100_000);
+}
+
+#[test]
+fn jobs_complete_on_all_workers() {
+    let pool = ThreadPool::new(4, 1000).expect("failed to create thread pool");
+    let jobs: Vec<_> = (0..32)
+        .map(|_| {
+            let mut executed = 0;
+            pool.spawn(move || {
+                while executed < 10_000 {
+                    sim::execute(100);
+                    executed += 100;
+                    if pool::should_yield() {
+                        return Step::Yield;
+                    }
+                }
+                Step::Done
+            })
+        })
+        .collect();
+
+    for job in jobs {
+        let stats = job.join().expect("job panicked");
+        assert_eq!(stats.ir, 10_000);
+        assert_eq!(stats.yields, 10);
+    }
+}
@@ -0,0 +1,95 @@
+//! Checks the lifetime of the rate-limited handlers with the simulated driver.
+
+use std::cell::Cell;
+use std::panic;
+use std::rc::Rc;
+
+use compiler_interrupts::{sim, LimitedFire, RateLimited};
+
+fn interrupt_handler(_ic: i64) {}
+
+#[test]
+fn keeps_handler_after_panic() {
+    let calls = Rc::new(Cell::new(0));
+    let counter = Rc::clone(&calls);
+    let handler = RateLimited::every(1, move |_: &LimitedFire| {
+        counter.set(counter.get() + 1);
+        if counter.get() == 1 {
+            panic!("handler failed");
+        }
+    });
+    unsafe {
+        handler.register(1000, 1000);
+    }
+
+    let result = panic::catch_unwind(|| sim::execute(1000));
+    assert!(result.is_err());
+
+    // the interrupts stay disabled after a panic until they are enabled again
+    unsafe {
+        compiler_interrupts::disable();
+        compiler_interrupts::enable();
+    }
+    sim::execute(1000);
+    assert_eq!(calls.get(), 2);
+
+    unsafe {
+        compiler_interrupts::deregister();
+    }
+}
+
+#[test]
+fn drops_handler_when_replaced() {
+    let state = Rc::new(());
+
+    // de-registered
+    let captured = Rc::clone(&state);
+    unsafe {
+        RateLimited::every(1, move |_: &LimitedFire| {
+            assert!(Rc::strong_count(&captured) > 1)
+        })
+        .register(1000, 1000);
+    }
+    assert_eq!(Rc::strong_count(&state), 2);
+    unsafe {
+        compiler_interrupts::deregister();
+    }
+    assert_eq!(Rc::strong_count(&state), 1);
+
+    // replaced by another handler
+    let captured = Rc::clone(&state);
+    unsafe {
+        RateLimited::every(1, move |_: &LimitedFire| {
+            assert!(Rc::strong_count(&captured) > 1)
+        })
+        .register(1000, 1000);
+        compiler_interrupts::register(1000, 1000, interrupt_handler);
+    }
+    assert_eq!(Rc::strong_count(&state), 1);
+
+    unsafe {
+        compiler_interrupts::deregister();
+    }
+}
+
+#[test]
+fn scoped_registration_restores_handler() {
+    let calls = Rc::new(Cell::new(0));
+    let counter = Rc::clone(&calls);
+    unsafe {
+        RateLimited::every(1, move |_: &LimitedFire| counter.set(counter.get() + 1))
+            .register(1000, 1000);
+    }
+
+    {
+        let _guard = unsafe { compiler_interrupts::register_scoped(1000, 1000, interrupt_handler) };
+        sim::execute(1000);
+    }
+    assert_eq!(calls.get(), 0);
+    sim::execute(1000);
+    assert_eq!(calls.get(), 1);
+
+    unsafe {
+        compiler_interrupts::deregister();
+    }
+}
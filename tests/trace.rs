@@ -0,0 +1,174 @@
+//! Checks the `tracing` integration with a capturing subscriber and the simulated driver.
+#![cfg(feature = "tracing")]
+
+use std::cell::Cell;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use compiler_interrupts::{sim, trace};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Metadata, Subscriber};
+
+thread_local! {
+    static EVENTS_PER_FIRE: Cell<usize> = const { Cell::new(1) };
+}
+
+/// Subscriber recording the spans and events as lines.
+#[derive(Clone, Default)]
+struct Capture {
+    lines: Arc<Mutex<Vec<String>>>,
+    spans: Arc<Mutex<Vec<&'static str>>>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl Capture {
+    fn push(&self, line: String) {
+        self.lines.lock().unwrap().push(line);
+    }
+
+    fn span(&self, id: &Id) -> &'static str {
+        self.spans.lock().unwrap()[id.into_u64() as usize - 1]
+    }
+
+    /// Returns the recorded lines since the last call.
+    fn take(&self) -> Vec<String> {
+        std::mem::take(&mut *self.lines.lock().unwrap())
+    }
+}
+
+/// Formats the fields of a span or event.
+#[derive(Default)]
+struct Fields(String);
+
+impl Visit for Fields {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if !self.0.is_empty() {
+            self.0.push(' ');
+        }
+        if field.name() == "message" {
+            self.0.push_str(&format!("{:?}", value));
+        } else {
+            self.0.push_str(&format!("{}={:?}", field.name(), value));
+        }
+    }
+}
+
+impl Subscriber for Capture {
+    fn enabled(&self, _: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, span: &Attributes<'_>) -> Id {
+        let mut fields = Fields::default();
+        span.record(&mut fields);
+        let name = span.metadata().name();
+        self.push(format!("new {} {}", name, fields.0).trim_end().to_string());
+        self.spans.lock().unwrap().push(name);
+        Id::from_u64(self.next_id.fetch_add(1, Ordering::Relaxed) + 1)
+    }
+
+    fn record(&self, _: &Id, _: &Record<'_>) {}
+
+    fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        self.push(format!("{} {}", event.metadata().level(), fields.0));
+    }
+
+    fn enter(&self, span: &Id) {
+        self.push(format!("enter {}", self.span(span)));
+    }
+
+    fn exit(&self, span: &Id) {
+        self.push(format!("exit {}", self.span(span)));
+    }
+}
+
+fn interrupt_handler(ic: i64) {
+    for _ in 0..EVENTS_PER_FIRE.with(Cell::get) {
+        trace::event(Level::INFO, "fired", ic);
+    }
+}
+
+#[test]
+fn buffers_events_and_emits_spans() {
+    let capture = Capture::default();
+    tracing::subscriber::with_default(capture.clone(), || {
+        unsafe {
+            compiler_interrupts::register(1000, 1000, interrupt_handler);
+        }
+        assert_eq!(
+            capture.take(),
+            [
+                "new ci_registered ir_interval=1000 cycles_interval=1000",
+                "enter ci_registered",
+                "DEBUG compiler interrupts registered",
+            ]
+        );
+
+        // the events are buffered inside the handler until the critical section ends
+        sim::execute(2000);
+        assert!(capture.take().is_empty());
+        unsafe {
+            compiler_interrupts::disable();
+        }
+        assert_eq!(capture.take(), ["new ci_disabled", "enter ci_disabled"]);
+        unsafe {
+            compiler_interrupts::enable();
+        }
+        assert_eq!(
+            capture.take(),
+            [
+                "exit ci_disabled",
+                "INFO fired value=1000",
+                "INFO fired value=1000",
+            ]
+        );
+
+        // the events beyond the capacity are counted
+        EVENTS_PER_FIRE.with(|events| events.set(trace::CAPACITY + 6));
+        sim::execute(1000);
+        assert!(capture.take().is_empty());
+        trace::flush();
+        let lines = capture.take();
+        assert_eq!(lines.len(), trace::CAPACITY + 1);
+        assert!(lines[..trace::CAPACITY]
+            .iter()
+            .all(|line| line == "INFO fired value=1000"));
+        assert_eq!(
+            lines[trace::CAPACITY],
+            "WARN compiler interrupts event buffer overflowed dropped=6"
+        );
+
+        // the events are flushed when the handler is de-registered
+        EVENTS_PER_FIRE.with(|events| events.set(1));
+        sim::execute(1000);
+        unsafe {
+            compiler_interrupts::deregister();
+        }
+        assert_eq!(
+            capture.take(),
+            [
+                "INFO fired value=1000",
+                "DEBUG compiler interrupts de-registered",
+                "exit ci_registered",
+            ]
+        );
+
+        // a handler de-registered inside an interrupt handler leaves its span at the next flush
+        unsafe {
+            compiler_interrupts::register_with_context(1000, 1000, |ctx| {
+                ctx.deregister_after_return()
+            });
+        }
+        assert_eq!(capture.take().len(), 3);
+        sim::execute(1000);
+        assert!(capture.take().is_empty());
+        trace::flush();
+        assert_eq!(capture.take(), ["exit ci_registered"]);
+    });
+}
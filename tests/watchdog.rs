@@ -0,0 +1,74 @@
+//! Checks that the watchdog reports the watched threads which stopped firing interrupts.
+
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use compiler_interrupts::sim;
+use compiler_interrupts::watchdog::{self, Watchdog};
+
+fn interrupt_handler(_ic: i64) {}
+
+#[test]
+fn reports_stalled_thread() {
+    let (stalls, stalled) = mpsc::channel();
+    let watchdog = Watchdog::spawn(Duration::from_millis(200), move |stall| {
+        let _ = stalls.send(stall.clone());
+    })
+    .expect("failed to spawn watchdog");
+
+    let (ready, started) = mpsc::channel();
+    let (release, released) = mpsc::channel::<()>();
+    let worker = thread::Builder::new()
+        .name("stalled-worker".into())
+        .spawn(move || unsafe {
+            compiler_interrupts::register(1000, 1000, interrupt_handler);
+            watchdog::watch();
+            sim::execute(10000);
+            compiler_interrupts::disable();
+            ready.send(()).unwrap();
+            // no interrupt points until released
+            released.recv().unwrap();
+            compiler_interrupts::enable();
+        })
+        .unwrap();
+
+    // keeps firing, hence it is never reported
+    let (stop, stopped) = mpsc::channel::<()>();
+    let busy = thread::Builder::new()
+        .name("busy-worker".into())
+        .spawn(move || {
+            unsafe {
+                compiler_interrupts::register(1000, 1000, interrupt_handler);
+            }
+            watchdog::watch();
+            while stopped.try_recv().is_err() {
+                sim::execute(1000);
+                thread::sleep(Duration::from_millis(5));
+            }
+        })
+        .unwrap();
+
+    started.recv().unwrap();
+    let stall = stalled
+        .recv_timeout(Duration::from_secs(5))
+        .expect("stalled thread was not reported");
+    assert_eq!(stall.thread, worker.thread().id());
+    assert_eq!(stall.name.as_deref(), Some("stalled-worker"));
+    assert_eq!(stall.disable_depth, 1);
+    assert!(stall.silent_for > Duration::from_millis(200));
+
+    let current = watchdog.stalled();
+    assert!(current.iter().any(|s| s.thread == worker.thread().id()));
+    assert!(current.iter().all(|s| s.thread != busy.thread().id()));
+
+    release.send(()).unwrap();
+    stop.send(()).unwrap();
+    worker.join().unwrap();
+    busy.join().unwrap();
+    drop(watchdog);
+
+    assert!(stalled
+        .try_iter()
+        .all(|s| s.name.as_deref() != Some("busy-worker")));
+}